@@ -10,24 +10,49 @@
 #![warn(clippy::use_self)]
 
 use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
     error::Error,
     fmt,
-    io::{Cursor, Read, Write},
-    num::ParseIntError,
-    path::PathBuf,
+    fs::File,
+    io::{BufWriter, Cursor, IsTerminal, Read, Seek, Write},
+    num::{ParseFloatError, ParseIntError},
+    path::{Path, PathBuf},
+    process,
     str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
-use clap::Parser;
-use image::{ImageFormat, ImageReader, RgbaImage};
+use clap::{error::ErrorKind, CommandFactory, Parser, ValueEnum};
+use image::{
+    codecs::{
+        gif::{GifDecoder, GifEncoder, Repeat},
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType as PngFilterType, PngEncoder},
+    },
+    imageops,
+    imageops::FilterType,
+    AnimationDecoder, DynamicImage, Frame, GrayImage, ImageError, ImageFormat, ImageReader,
+    ImageResult, Rgba32FImage, RgbaImage,
+};
 use mark::{
-    bw,
+    adjust, ase, bw, compare,
     dither::{
-        AlgoFloydSteinberg, AlgoRandom, AlgoStucki, AlgoThreshold, Algorithm, DiffCiede2000,
-        DiffClamp, DiffEuclid, DiffHyAb, DiffManhattan, Difference, Palette,
+        self, Algorithm, AlgorithmKind, AlphaMode, ColorSpace, Control, DiffCie94, DiffCmc,
+        Difference, DifferenceKind, DiffusionOrigin, Dispatch, DispatchColor, HueChannel, Options,
+        Palette, PaletteError,
     },
+    invert,
+    palette::{builtin, Representative},
+    posterize, resize, thermal,
 };
-use palette::{Clamp, IntoColor, Lab, Lch, LinSrgb, Luv, Okhsl, Okhsv, Oklab, Srgb};
+use palette::{FromColor, IntoColor, Lab, LinSrgb, Srgb, Xyz};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::{Serialize, Serializer};
 
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
 enum BwMethod {
@@ -37,41 +62,583 @@ enum BwMethod {
     Hsv,
     Cielab,
     Oklab,
+    Hsp,
+    Rec601Luma,
+    Rec709Luma,
+    /// Custom channel weights, see --weights.
+    Weighted,
+    Lightness,
+    Value,
+    Luminosity,
 }
 
-impl From<BwMethod> for bw::Method {
-    fn from(value: BwMethod) -> Self {
-        match value {
-            BwMethod::SrgbAverage => Self::SrgbAverage,
-            BwMethod::LinSrgbAverage => Self::LinSrgbAverage,
-            BwMethod::Hsl => Self::Hsl,
-            BwMethod::Hsv => Self::Hsv,
-            BwMethod::Cielab => Self::Cielab,
-            BwMethod::Oklab => Self::Oklab,
+#[derive(Debug, Clone, Copy)]
+struct Weights([f32; 3]);
+
+#[derive(Debug)]
+enum ParseWeightsError {
+    MustHaveThreeValues,
+    ParseFloatError(ParseFloatError),
+}
+
+impl fmt::Display for ParseWeightsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MustHaveThreeValues => write!(f, "weights must have the form R,G,B"),
+            Self::ParseFloatError(e) => e.fmt(f),
         }
     }
 }
 
-#[derive(Debug, clap::Parser)]
+impl Error for ParseWeightsError {}
+
+impl From<ParseFloatError> for ParseWeightsError {
+    fn from(value: ParseFloatError) -> Self {
+        Self::ParseFloatError(value)
+    }
+}
+
+impl FromStr for Weights {
+    type Err = ParseWeightsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights = s
+            .split(',')
+            .map(|w| w.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let weights = weights
+            .try_into()
+            .map_err(|_| ParseWeightsError::MustHaveThreeValues)?;
+        Ok(Self(weights))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Levels([u32; 3]);
+
+#[derive(Debug)]
+enum ParseLevelsError {
+    MustHaveOneOrThreeValues,
+    OutOfRange,
+    ParseIntError(ParseIntError),
+}
+
+impl fmt::Display for ParseLevelsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MustHaveOneOrThreeValues => write!(f, "levels must have the form N or R,G,B"),
+            Self::OutOfRange => write!(f, "levels must be between 2 and 256"),
+            Self::ParseIntError(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ParseLevelsError {}
+
+impl From<ParseIntError> for ParseLevelsError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl FromStr for Levels {
+    type Err = ParseLevelsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let levels = s
+            .split(',')
+            .map(|l| l.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let levels = match levels[..] {
+            [l] => [l, l, l],
+            [r, g, b] => [r, g, b],
+            _ => return Err(ParseLevelsError::MustHaveOneOrThreeValues),
+        };
+        if !levels.iter().all(|l| (2..=256).contains(l)) {
+            return Err(ParseLevelsError::OutOfRange);
+        }
+        Ok(Self(levels))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CmcRatio(DiffCmc);
+
+#[derive(Debug)]
+enum ParseCmcRatioError {
+    MustHaveTwoValues,
+    MustBePositive,
+    ParseFloatError(ParseFloatError),
+}
+
+impl fmt::Display for ParseCmcRatioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MustHaveTwoValues => write!(f, "ratio must have the form L:C"),
+            Self::MustBePositive => write!(f, "ratio must consist of positive numbers"),
+            Self::ParseFloatError(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ParseCmcRatioError {}
+
+impl From<ParseFloatError> for ParseCmcRatioError {
+    fn from(value: ParseFloatError) -> Self {
+        Self::ParseFloatError(value)
+    }
+}
+
+impl FromStr for CmcRatio {
+    type Err = ParseCmcRatioError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (l, c) = s
+            .split_once(':')
+            .ok_or(ParseCmcRatioError::MustHaveTwoValues)?;
+        let (l, c) = (l.parse::<f32>()?, c.parse::<f32>()?);
+        if !(l > 0.0 && c > 0.0 && l.is_finite() && c.is_finite()) {
+            return Err(ParseCmcRatioError::MustBePositive);
+        }
+        Ok(Self(DiffCmc { l, c }))
+    }
+}
+
+impl BwMethod {
+    fn to_method(self, weights: Option<Weights>) -> bw::Method {
+        match self {
+            Self::SrgbAverage => bw::Method::SrgbAverage,
+            Self::LinSrgbAverage => bw::Method::LinSrgbAverage,
+            Self::Hsl => bw::Method::Hsl,
+            Self::Hsv => bw::Method::Hsv,
+            Self::Cielab => bw::Method::Cielab,
+            Self::Oklab => bw::Method::Oklab,
+            Self::Hsp => bw::Method::Hsp,
+            Self::Rec601Luma => bw::Method::Rec601Luma,
+            Self::Rec709Luma => bw::Method::Rec709Luma,
+            Self::Weighted => {
+                let [r, g, b] = weights.expect("weights are required").0;
+                bw::Method::Weighted { r, g, b }
+            }
+            Self::Lightness => bw::Method::Lightness,
+            Self::Value => bw::Method::Value,
+            Self::Luminosity => bw::Method::Luminosity,
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Parser)]
 /// Convert images into black and white.
 struct BwCmd {
     #[arg(long, short)]
     method: BwMethod,
+    /// Weights of the srgb channels as R,G,B for --method weighted.
+    #[arg(long, required_if_eq("method", "weighted"))]
+    weights: Option<Weights>,
+    /// Leave fully transparent pixels untouched.
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    skip_transparent: bool,
+    /// Tone the result with a gradient from this color for black...
+    #[arg(long, requires = "tone_highlight")]
+    tone_shadow: Option<SrgbColor>,
+    /// ... to this color for white.
+    #[arg(long, requires = "tone_shadow")]
+    tone_highlight: Option<SrgbColor>,
+    /// Tone the result sepia, like --tone-shadow 2b1d0e --tone-highlight f0e0b0.
+    #[arg(long, conflicts_with_all = ["tone_shadow", "tone_highlight"])]
+    sepia: bool,
 }
 
 impl BwCmd {
+    fn tone(&self) -> Option<(Srgb<u8>, Srgb<u8>)> {
+        if self.sepia {
+            return Some((Srgb::new(0x2b, 0x1d, 0x0e), Srgb::new(0xf0, 0xe0, 0xb0)));
+        }
+        Some((self.tone_shadow?.0, self.tone_highlight?.0))
+    }
+
+    fn run(self, mut image: RgbaImage) -> RgbaImage {
+        let method = self.method.to_method(self.weights);
+        if self.skip_transparent {
+            bw::bw_skip_transparent(&mut image, method);
+        } else {
+            bw::bw(&mut image, method);
+        }
+        if let Some((shadow, highlight)) = self.tone() {
+            let (shadow, highlight) = (shadow.into_format(), highlight.into_format());
+            if self.skip_transparent {
+                bw::tone_skip_transparent(&mut image, shadow, highlight);
+            } else {
+                bw::tone(&mut image, shadow, highlight);
+            }
+        }
+        image
+    }
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+/// Reduce each srgb channel to a few evenly spaced values, without a palette.
+struct PosterizeCmd {
+    /// Number of levels per channel, either N or R,G,B.
+    #[arg(long, short)]
+    levels: Levels,
+}
+
+impl PosterizeCmd {
+    fn run(self, mut image: RgbaImage) -> RgbaImage {
+        posterize::posterize(&mut image, self.levels.0);
+        image
+    }
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+/// Invert the colors of images, producing negatives.
+struct InvertCmd {
+    /// Invert linear instead of gamma-encoded srgb channels.
+    #[arg(long)]
+    linear: bool,
+}
+
+impl InvertCmd {
     fn run(self, mut image: RgbaImage) -> RgbaImage {
-        bw::bw(&mut image, self.method.into());
+        invert::invert(&mut image, self.linear);
         image
     }
 }
 
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum PrinterProfile {
+    /// Receipt printers with coarse dots that bleed a lot.
+    Receipt,
+    /// Label printers with finer and more precise dots.
+    Label,
+}
+
+impl From<PrinterProfile> for thermal::Profile {
+    fn from(value: PrinterProfile) -> Self {
+        match value {
+            PrinterProfile::Receipt => Self::Receipt,
+            PrinterProfile::Label => Self::Label,
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+/// Dither images for printing on thermal printers.
+///
+/// Outputs black dots on white paper, adjusted for how the printer's dots bleed.
+struct ThermalCmd {
+    #[arg(long, default_value = "receipt")]
+    printer_profile: PrinterProfile,
+}
+
+impl ThermalCmd {
+    fn run(self, image: RgbaImage, control: &Control) -> RgbaImage {
+        thermal::thermal(image, self.printer_profile.into(), control)
+            .unwrap_or_else(|e| exit_with_error(e))
+    }
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+/// Print information about an image instead of writing one.
+///
+/// Every line has the form "key: value".
+struct InfoCmd {
+    /// Also list this many of the most common colors.
+    #[arg(long, default_value_t = 8)]
+    top_colors: usize,
+    /// Print the information as a single json object instead.
+    #[arg(long)]
+    json: bool,
+}
+
+/// The report of [`InfoCmd`].
+#[derive(Serialize)]
+struct InfoReport {
+    width: u32,
+    height: u32,
+    color_type: String,
+    has_alpha: bool,
+    unique_colors: usize,
+    min_luminance: Option<f32>,
+    max_luminance: Option<f32>,
+    top_colors: Vec<ColorCount>,
+}
+
+#[derive(Serialize)]
+struct ColorCount {
+    color: SrgbColor,
+    count: usize,
+}
+
+impl InfoCmd {
+    fn run(self, image: &DynamicImage) {
+        let rgba = image.to_rgba8();
+        let mut counts = HashMap::<[u8; 3], usize>::new();
+        for pixel in rgba.pixels() {
+            let [r, g, b, _] = pixel.0;
+            *counts.entry([r, g, b]).or_default() += 1;
+        }
+        let luminances = rgba.pixels().map(|pixel| {
+            let [r, g, b, _] = pixel.0;
+            let color: LinSrgb = Srgb::new(r, g, b).into_linear();
+            0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue
+        });
+        let min_luminance = luminances.clone().fold(f32::INFINITY, f32::min);
+        let max_luminance = luminances.fold(f32::NEG_INFINITY, f32::max);
+        let unique_colors = counts.len();
+
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by_key(|&(color, count)| (Reverse(count), color));
+        let top_colors = (counts.into_iter().take(self.top_colors))
+            .map(|([r, g, b], count)| ColorCount {
+                color: SrgbColor(Srgb::new(r, g, b)),
+                count,
+            })
+            .collect();
+
+        let report = InfoReport {
+            width: image.width(),
+            height: image.height(),
+            color_type: format!("{:?}", image.color()),
+            has_alpha: image.color().has_alpha(),
+            unique_colors,
+            min_luminance: (unique_colors > 0).then_some(min_luminance),
+            max_luminance: (unique_colors > 0).then_some(max_luminance),
+            top_colors,
+        };
+        if self.json {
+            println!("{}", to_json(&report));
+            return;
+        }
+
+        println!("width: {}", report.width);
+        println!("height: {}", report.height);
+        println!("color_type: {}", report.color_type);
+        println!("has_alpha: {}", report.has_alpha);
+        println!("unique_colors: {}", report.unique_colors);
+        if let (Some(min), Some(max)) = (report.min_luminance, report.max_luminance) {
+            println!("min_luminance: {min:.4}");
+            println!("max_luminance: {max:.4}");
+        }
+        for ColorCount { color, count } in report.top_colors {
+            println!("color: {color} {count}");
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum PaletteRepresentative {
+    /// The average of all colors. Smooths out the palette.
+    Mean,
+    /// The per-channel median of all colors.
+    Median,
+    /// The most frequent color. Preserves actual image colors.
+    Mode,
+}
+
+impl From<PaletteRepresentative> for Representative {
+    fn from(value: PaletteRepresentative) -> Self {
+        match value {
+            PaletteRepresentative::Mean => Self::Mean,
+            PaletteRepresentative::Median => Self::Median,
+            PaletteRepresentative::Mode => Self::Mode,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum BuiltinPalette {
+    BlackWhite,
+    #[value(name = "grayscale-4")]
+    Grayscale4,
+    Gameboy,
+    Cga,
+    Ega,
+    WebSafe,
+}
+
+impl BuiltinPalette {
+    fn colors(self) -> &'static [Srgb<u8>] {
+        match self {
+            Self::BlackWhite => &builtin::BLACK_WHITE,
+            Self::Grayscale4 => &builtin::GRAYSCALE_4,
+            Self::Gameboy => &builtin::GAMEBOY,
+            Self::Cga => &builtin::CGA,
+            Self::Ega => &builtin::EGA,
+            Self::WebSafe => &builtin::WEB_SAFE,
+        }
+    }
+}
+
+fn list_palettes() {
+    for palette in BuiltinPalette::value_variants() {
+        let name = palette.to_possible_value().expect("no variant is skipped");
+        println!("{:<12} {} colors", name.get_name(), palette.colors().len());
+    }
+}
+
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
 enum DitherAlgorithm {
+    /// Replace every pixel with its nearest palette color without dithering.
+    #[value(alias = "remap", alias = "nearest")]
     Threshold,
     Random,
     FloydSteinberg,
+    /// Floyd-Steinberg diffusing error only horizontally.
+    #[value(name = "floyd-steinberg-1d")]
+    FloydSteinberg1D,
     Stucki,
+    /// Jarvis-Judice-Ninke.
+    Jarvis,
+    Burkes,
+    Sierra,
+    SierraTwoRow,
+    SierraLite,
+    Atkinson,
+    /// Ordered dithering with a Bayer matrix, see --matrix-size.
+    Bayer,
+    /// Ordered dithering with the 2x2 Bayer matrix, for the classic 50%
+    /// checkerboard with two colors.
+    Checkerboard,
+    /// Ordered dithering with clustered dots like a print halftone, see
+    /// --cell-size.
+    Halftone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Depth {
+    #[value(name = "8")]
+    Eight,
+    #[value(name = "16")]
+    Sixteen,
+}
+
+/// How much to print to stderr besides warnings and errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+impl Verbosity {
+    /// Whether to print what is being loaded, processed and written.
+    fn progress(self) -> bool {
+        self != Self::Quiet
+    }
+
+    /// Run a step, printing how long it took if verbose.
+    fn timed<T>(self, step: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        if self == Self::Verbose {
+            eprintln!("{step} took {:.1?}", start.elapsed());
+        }
+        result
+    }
+}
+
+/// Runs shorter than this don't report their progress.
+const PROGRESS_DELAY: Duration = Duration::from_secs(1);
+/// How often the progress line on a terminal is updated.
+const PROGRESS_TERMINAL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a progress line is printed when stderr is not a terminal.
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reports how many rows a long running algorithm has processed.
+///
+/// On a terminal, a single line is updated in place. Otherwise, a new line is
+/// printed every once in a while.
+struct RowProgress {
+    total: u64,
+    done: AtomicU64,
+    start: Instant,
+    /// When the progress was last printed, if ever.
+    printed: Mutex<Option<Instant>>,
+    terminal: bool,
+}
+
+impl RowProgress {
+    fn new(total: u64) -> Self {
+        Self {
+            total,
+            done: AtomicU64::new(0),
+            start: Instant::now(),
+            printed: Mutex::new(None),
+            terminal: std::io::stderr().is_terminal(),
+        }
+    }
+
+    fn print(&self, done: u64) {
+        let total = self.total;
+        let percent = done.min(total) * 100 / total.max(1);
+        if self.terminal {
+            eprint!("\rDithering row {done}/{total} ({percent}%)");
+        } else {
+            eprintln!("Dithering row {done}/{total} ({percent}%)");
+        }
+    }
+
+    fn row(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = Instant::now();
+        if now - self.start < PROGRESS_DELAY {
+            return;
+        }
+        // Rows processed in parallel skip reporting instead of waiting.
+        let Ok(mut printed) = self.printed.try_lock() else {
+            return;
+        };
+        let interval = if self.terminal {
+            PROGRESS_TERMINAL_INTERVAL
+        } else {
+            PROGRESS_LOG_INTERVAL
+        };
+        if printed.is_some_and(|last| now - last < interval) {
+            return;
+        }
+        *printed = Some(now);
+        self.print(done);
+    }
+
+    /// Complete the line updated in place on a terminal.
+    fn finish(&self) {
+        let printed = self.printed.lock().expect("progress lock was poisoned");
+        if self.terminal && printed.is_some() {
+            self.print(self.done.load(Ordering::Relaxed));
+            eprintln!();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum MatrixSize {
+    #[value(name = "2")]
+    Two,
+    #[value(name = "4")]
+    Four,
+    #[value(name = "8")]
+    Eight,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum DitherAlpha {
+    /// Leave the alpha channel as it is.
+    Preserve,
+    /// Make pixels opaque or transparent depending on --alpha-cutoff.
+    Threshold,
+    /// Dither the alpha channel to opaque and transparent like the color.
+    Dither,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum DitherDiffusionOrigin {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
 }
 
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
@@ -80,9 +647,12 @@ enum DitherColorSpace {
     LinSrgb,
     Cielab,
     Cieluv,
+    /// Cylindrical, with a hue angle in degrees that wraps around.
     Cielch,
     Oklab,
+    /// Cylindrical, with a hue angle in degrees that wraps around.
     Okhsl,
+    /// Cylindrical, with a hue angle in degrees that wraps around.
     Okhsv,
 }
 
@@ -94,8 +664,81 @@ enum DitherDifference {
     HyAbClamp,
     Ciede2000,
     Ciede2000Clamp,
+    Cie76,
+    Cie76Clamp,
+    Cie94,
+    Cie94Clamp,
+    /// Euclidean distance in oklab, the same as euclid with --color-space
+    /// oklab.
+    Oklab,
+    OklabClamp,
+    /// CMC l:c with the weights from --cmc-ratio.
+    Cmc,
+    CmcClamp,
+    Redmean,
+    RedmeanClamp,
     Manhattan,
     ManhattanClamp,
+    /// Euclidean distance with the weights from --channel-weights.
+    WeightedEuclid,
+    WeightedEuclidClamp,
+}
+
+impl DitherDifference {
+    /// Whether the difference converts colors to cielab before comparing them.
+    fn is_cielab_based(self) -> bool {
+        matches!(
+            self,
+            Self::HyAb
+                | Self::HyAbClamp
+                | Self::Ciede2000
+                | Self::Ciede2000Clamp
+                | Self::Cie76
+                | Self::Cie76Clamp
+                | Self::Cie94
+                | Self::Cie94Clamp
+                | Self::Cmc
+                | Self::CmcClamp
+        )
+    }
+
+    /// Whether the difference converts colors to oklab before comparing them.
+    fn is_oklab_based(self) -> bool {
+        matches!(self, Self::Oklab | Self::OklabClamp)
+    }
+
+    /// Whether the difference converts colors to srgb before comparing them.
+    fn is_srgb_based(self) -> bool {
+        matches!(self, Self::Redmean | Self::RedmeanClamp)
+    }
+}
+
+/// Explain why a color space and difference are a poor combination.
+fn combination_warning(space: DitherColorSpace, diff: DitherDifference) -> Option<&'static str> {
+    use DitherColorSpace::*;
+    if diff.is_cielab_based() {
+        return match space {
+            Cielab => None,
+            _ => Some("this difference converts to cielab for every comparison, consider --color-space cielab"),
+        };
+    }
+    if diff.is_oklab_based() {
+        return match space {
+            Oklab => Some("this difference is the same as euclid in oklab, which is faster"),
+            _ => Some("this difference converts to oklab for every comparison, consider --color-space oklab"),
+        };
+    }
+    if diff.is_srgb_based() {
+        return match space {
+            Srgb => None,
+            _ => Some("this difference converts to srgb for every comparison, consider --color-space srgb"),
+        };
+    }
+    match space {
+        Srgb | LinSrgb => Some("distances in srgb are not perceptually uniform, consider --color-space oklab or cielab"),
+        Cielch | Okhsl | Okhsv => Some("this color space has a hue angle in degrees, which dominates distances"),
+        Cielab | Cieluv | Oklab => None,
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -103,14 +746,17 @@ struct SrgbColor(Srgb<u8>);
 
 #[derive(Debug)]
 enum ParseSrgbColorError {
-    MustBeSixHexDigits,
+    MustBeHexDigits,
     ParseIntError(ParseIntError),
 }
 
 impl fmt::Display for ParseSrgbColorError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::MustBeSixHexDigits => write!(f, "a color must consist of six hexadecimal digits"),
+            Self::MustBeHexDigits => write!(
+                f,
+                "a color must consist of three or six hexadecimal digits, optionally preceded by #"
+            ),
             Self::ParseIntError(e) => e.fmt(f),
         }
     }
@@ -124,16 +770,35 @@ impl From<ParseIntError> for ParseSrgbColorError {
     }
 }
 
+impl fmt::Display for SrgbColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Srgb {
+            red, green, blue, ..
+        } = self.0;
+        write!(f, "{red:02x}{green:02x}{blue:02x}")
+    }
+}
+
+impl Serialize for SrgbColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl FromStr for SrgbColor {
     type Err = ParseSrgbColorError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() != 6 {
-            return Err(ParseSrgbColorError::MustBeSixHexDigits);
-        }
+        let s = s.strip_prefix('#').unwrap_or(s);
         if !s.chars().all(|c| c.is_ascii_hexdigit()) {
-            return Err(ParseSrgbColorError::MustBeSixHexDigits);
+            return Err(ParseSrgbColorError::MustBeHexDigits);
         }
+        // Shorthand like abc stands for aabbcc.
+        let s = match s.len() {
+            3 => s.chars().flat_map(|c| [c, c]).collect(),
+            6 => s.to_string(),
+            _ => return Err(ParseSrgbColorError::MustBeHexDigits),
+        };
         let r = u8::from_str_radix(&s[0..2], 16)?;
         let g = u8::from_str_radix(&s[2..4], 16)?;
         let b = u8::from_str_radix(&s[4..6], 16)?;
@@ -141,160 +806,2609 @@ impl FromStr for SrgbColor {
     }
 }
 
-#[derive(Debug, clap::Parser)]
-/// Dither images.
-struct DitherCmd {
-    #[arg(long, short)]
-    algorithm: DitherAlgorithm,
-    #[arg(long, short)]
-    color_space: DitherColorSpace,
-    #[arg(long, short)]
-    difference: DitherDifference,
-    /// Add a hex color to the palette used for dithering.
-    #[arg(long, short)]
-    palette: Vec<SrgbColor>,
+#[derive(Debug, Clone, Copy)]
+struct Size {
+    width: u32,
+    height: u32,
 }
 
-impl DitherCmd {
-    fn run(self, image: RgbaImage) -> RgbaImage {
-        match self.color_space {
-            DitherColorSpace::Srgb => self.run_c::<Srgb>(image),
-            DitherColorSpace::LinSrgb => self.run_c::<LinSrgb>(image),
-            DitherColorSpace::Cielab => self.run_c::<Lab>(image),
-            DitherColorSpace::Cieluv => self.run_c::<Lch>(image),
-            DitherColorSpace::Cielch => self.run_c::<Luv>(image),
-            DitherColorSpace::Oklab => self.run_c::<Oklab>(image),
-            DitherColorSpace::Okhsl => self.run_c::<Okhsl>(image),
-            DitherColorSpace::Okhsv => self.run_c::<Okhsv>(image),
-        }
-    }
+#[derive(Debug)]
+enum ParseSizeError {
+    MustBeWidthXHeight,
+    MustBeNonzero,
+    ParseIntError(ParseIntError),
+}
 
-    fn run_c<C>(self, image: RgbaImage) -> RgbaImage
-    where
-        C: AsMut<[f32; 3]>,
-        C: AsRef<[f32; 3]>,
-        C: Clamp,
-        C: Copy,
-        C: IntoColor<Lab>,
-        C: IntoColor<Srgb>,
-        Srgb: IntoColor<C>,
-    {
-        use DitherDifference::*;
-        match self.difference {
-            Euclid => self.run_cd::<C, DiffEuclid>(image),
-            EuclidClamp => self.run_cd::<C, DiffClamp<DiffEuclid>>(image),
-            HyAb => self.run_cd::<C, DiffHyAb>(image),
-            HyAbClamp => self.run_cd::<C, DiffClamp<DiffHyAb>>(image),
-            Ciede2000 => self.run_cd::<C, DiffCiede2000>(image),
-            Ciede2000Clamp => self.run_cd::<C, DiffClamp<DiffCiede2000>>(image),
-            Manhattan => self.run_cd::<C, DiffManhattan>(image),
-            ManhattanClamp => self.run_cd::<C, DiffClamp<DiffManhattan>>(image),
+impl fmt::Display for ParseSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MustBeWidthXHeight => write!(f, "a size must have the form WxH"),
+            Self::MustBeNonzero => write!(f, "a size must not be zero"),
+            Self::ParseIntError(e) => e.fmt(f),
         }
     }
+}
 
-    fn run_cd<C, D>(self, image: RgbaImage) -> RgbaImage
-    where
-        C: AsMut<[f32; 3]>,
-        C: Clamp,
-        C: Copy,
-        C: IntoColor<Srgb>,
-        D: Difference<C>,
-        Srgb: IntoColor<C>,
-    {
-        use DitherAlgorithm::*;
-        match self.algorithm {
-            Threshold => self.run_acd::<AlgoThreshold, C, D>(image),
-            Random => self.run_acd::<AlgoRandom, C, D>(image),
-            FloydSteinberg => self.run_acd::<AlgoFloydSteinberg, C, D>(image),
-            Stucki => self.run_acd::<AlgoStucki, C, D>(image),
-        }
-    }
+impl Error for ParseSizeError {}
 
-    fn run_acd<A, C, D>(self, image: RgbaImage) -> RgbaImage
-    where
-        A: Algorithm<C, D>,
-        Srgb: IntoColor<C>,
-    {
-        let colors = self
-            .palette
-            .into_iter()
-            .map(|c| c.0.into_format().into_color())
-            .collect::<Vec<C>>();
-        let palette = Palette::<C>::new(colors);
-        A::run(image, &palette)
+impl From<ParseIntError> for ParseSizeError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
     }
 }
 
-#[derive(Debug, clap::Parser)]
-enum Cmd {
-    Bw(BwCmd),
-    Dither(DitherCmd),
-}
+impl FromStr for Size {
+    type Err = ParseSizeError;
 
-impl Cmd {
-    fn run(self, image: RgbaImage) -> RgbaImage {
-        match self {
-            Self::Bw(cmd) => cmd.run(image),
-            Self::Dither(cmd) => cmd.run(image),
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once('x')
+            .ok_or(ParseSizeError::MustBeWidthXHeight)?;
+        let width = width.parse::<u32>()?;
+        let height = height.parse::<u32>()?;
+        if width == 0 || height == 0 {
+            return Err(ParseSizeError::MustBeNonzero);
         }
+        Ok(Self { width, height })
     }
 }
 
-#[derive(Debug, clap::Parser)]
-struct Args {
-    /// Load image from file instead of stdin.
+#[derive(Debug, Clone, Copy)]
+struct PixelPos {
+    x: u32,
+    y: u32,
+}
+
+#[derive(Debug)]
+enum ParsePixelPosError {
+    MustBeXCommaY,
+    ParseIntError(ParseIntError),
+}
+
+impl fmt::Display for ParsePixelPosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MustBeXCommaY => write!(f, "a pixel position must have the form X,Y"),
+            Self::ParseIntError(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ParsePixelPosError {}
+
+impl From<ParseIntError> for ParsePixelPosError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl FromStr for PixelPos {
+    type Err = ParsePixelPosError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = s.split_once(',').ok_or(ParsePixelPosError::MustBeXCommaY)?;
+        let x = x.parse::<u32>()?;
+        let y = y.parse::<u32>()?;
+        Ok(Self { x, y })
+    }
+}
+
+/// Amount of colors of a generated palette.
+#[derive(Debug, Clone, Copy)]
+enum PaletteSize {
+    /// Suggested based on the colors of the image.
+    Auto,
+    Colors(usize),
+}
+
+impl PaletteSize {
+    fn for_image(self, image: &RgbaImage, verbosity: Verbosity) -> usize {
+        match self {
+            Self::Auto => {
+                let n = mark::palette::suggest_palette_size(image);
+                if verbosity.progress() {
+                    eprintln!("Using {n} palette colors");
+                }
+                n
+            }
+            Self::Colors(n) => n,
+        }
+    }
+}
+
+impl FromStr for PaletteSize {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Self::Auto);
+        }
+        Ok(Self::Colors(s.parse()?))
+    }
+}
+
+/// A non-negative duration given in seconds.
+#[derive(Debug, Clone, Copy)]
+struct Seconds(Duration);
+
+#[derive(Debug)]
+enum ParseSecondsError {
+    OutOfRange,
+    ParseFloatError(ParseFloatError),
+}
+
+impl fmt::Display for ParseSecondsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange => {
+                write!(f, "a duration must be a non-negative number of seconds")
+            }
+            Self::ParseFloatError(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ParseSecondsError {}
+
+impl From<ParseFloatError> for ParseSecondsError {
+    fn from(value: ParseFloatError) -> Self {
+        Self::ParseFloatError(value)
+    }
+}
+
+impl FromStr for Seconds {
+    type Err = ParseSecondsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let duration = Duration::try_from_secs_f64(s.parse()?);
+        Ok(Self(duration.map_err(|_| ParseSecondsError::OutOfRange)?))
+    }
+}
+
+/// Noise radius per channel of the color space.
+#[derive(Debug, Clone, Copy)]
+struct NoiseRadius([f32; 3]);
+
+/// Largest accepted noise radius, far beyond the range of any color space.
+const NOISE_RADIUS_MAX: f32 = 1000.0;
+
+#[derive(Debug)]
+enum ParseNoiseRadiusError {
+    MustHaveOneOrThreeValues,
+    MustNotBeNegative,
+    TooLarge,
+    ParseFloatError(ParseFloatError),
+}
+
+impl fmt::Display for ParseNoiseRadiusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MustHaveOneOrThreeValues => {
+                write!(f, "a noise radius must have the form R or R1,R2,R3")
+            }
+            Self::MustNotBeNegative => write!(f, "a noise radius must not be negative"),
+            Self::TooLarge => write!(f, "a noise radius must be at most {NOISE_RADIUS_MAX}"),
+            Self::ParseFloatError(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ParseNoiseRadiusError {}
+
+impl From<ParseFloatError> for ParseNoiseRadiusError {
+    fn from(value: ParseFloatError) -> Self {
+        Self::ParseFloatError(value)
+    }
+}
+
+impl FromStr for NoiseRadius {
+    type Err = ParseNoiseRadiusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let radii = s
+            .split(',')
+            .map(|r| r.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let radii = match radii[..] {
+            [r] => [r; 3],
+            [r1, r2, r3] => [r1, r2, r3],
+            _ => return Err(ParseNoiseRadiusError::MustHaveOneOrThreeValues),
+        };
+        if radii.iter().any(|r| r.is_nan() || *r < 0.0) {
+            return Err(ParseNoiseRadiusError::MustNotBeNegative);
+        }
+        if radii.iter().any(|r| *r > NOISE_RADIUS_MAX) {
+            return Err(ParseNoiseRadiusError::TooLarge);
+        }
+        Ok(Self(radii))
+    }
+}
+
+/// Maximum number of iterations of --auto-palette-kmeans.
+const KMEANS_MAX_ITERATIONS: usize = 32;
+
+/// Images with more colors than this are rejected by --palette-from.
+const PALETTE_FROM_MAX_COLORS: usize = 256;
+
+#[derive(Debug, Clone, clap::Parser)]
+/// Dither images.
+#[command(group(
+    clap::ArgGroup::new("palette_source")
+        .required(true)
+        .multiple(true)
+        .args(["palette", "palette_file", "palette_from", "builtin_palette", "auto_palette", "auto_palette_kmeans", "tile_palette"])
+))]
+struct DitherCmd {
+    #[arg(long, short)]
+    algorithm: DitherAlgorithm,
+    /// Size of the matrix used by the bayer algorithm.
+    #[arg(long, default_value = "4")]
+    matrix_size: MatrixSize,
+    /// Distance in pixels between the dots of the halftone algorithm.
+    #[arg(
+        long,
+        default_value = "8",
+        value_parser = clap::value_parser!(u32).range(1..=i64::from(dither::MAX_CELL_SIZE)),
+    )]
+    cell_size: u32,
+    #[arg(long, short)]
+    color_space: DitherColorSpace,
+    #[arg(long, short)]
+    difference: DitherDifference,
+    /// Weights of the color space channels as W1,W2,W3 for --difference
+    /// weighted-euclid.
+    #[arg(
+        long,
+        required_if_eq_any = [
+            ("difference", "weighted-euclid"),
+            ("difference", "weighted-euclid-clamp"),
+        ]
+    )]
+    channel_weights: Option<Weights>,
+    /// Use the textile instead of the graphic arts weighting factors for
+    /// --difference cie94.
+    #[arg(long)]
+    cie94_textiles: bool,
+    /// Lightness to chroma weighting as L:C for --difference cmc, commonly
+    /// 2:1 for acceptability or 1:1 for perceptibility.
+    #[arg(long, default_value = "2:1")]
+    cmc_ratio: CmcRatio,
+    /// Lightness, chroma and hue weighting factors as KL,KC,KH for
+    /// --difference ciede2000, for example 2,1,1 for textiles.
+    #[arg(long)]
+    de00_weights: Option<Weights>,
+    /// Add a hex color like ff8000, #ff8000 or #f80 to the palette used for
+    /// dithering.
+    #[arg(long, short)]
+    palette: Vec<SrgbColor>,
+    /// Add the colors of a palette file to the palette used for dithering.
+    ///
+    /// Either an Adobe Swatch Exchange (.ase) file or a text file with one hex
+    /// color per line.
+    #[arg(long)]
+    palette_file: Option<PathBuf>,
+    /// Add the unique colors of an image to the palette used for dithering.
+    ///
+    /// Fully transparent pixels are ignored. The image may have at most 256
+    /// colors, like pixel art or gif images.
+    #[arg(long)]
+    palette_from: Option<PathBuf>,
+    /// Add the colors of a builtin palette to the palette used for dithering.
+    ///
+    /// See --list-palettes for all builtin palettes.
+    #[arg(long)]
+    builtin_palette: Option<BuiltinPalette>,
+    /// Generate a palette with this amount of colors from the image using
+    /// median-cut quantization.
+    ///
+    /// With "auto", the amount is chosen based on how the colors of the image
+    /// are distributed.
+    #[arg(long, conflicts_with_all = ["palette", "palette_file", "palette_from", "builtin_palette", "tile_palette"])]
+    auto_palette: Option<PaletteSize>,
+    /// Generate a palette with this amount of colors from the image using
+    /// k-means clustering in the color space, seeded by --seed.
+    ///
+    /// Accepts "auto" like --auto-palette.
+    #[arg(
+        long,
+        conflicts_with_all = ["palette", "palette_file", "palette_from", "builtin_palette", "tile_palette", "auto_palette"]
+    )]
+    auto_palette_kmeans: Option<PaletteSize>,
+    /// Split the image into tiles of size WxH and dither each tile against its
+    /// own automatically generated palette.
+    ///
+    /// Each tile is dithered together with half a tile of its surroundings,
+    /// from which its palette is generated as well. Where these regions
+    /// overlap, pixels are taken from either tile with a probability that
+    /// falls off linearly towards the neighbouring tile's center, so the
+    /// palettes blend smoothly instead of meeting at visible seams.
+    #[arg(long, conflicts_with_all = ["palette", "palette_file", "palette_from", "builtin_palette"])]
+    tile_palette: Option<Size>,
+    /// Amount of colors in each tile's palette.
+    #[arg(long, default_value_t = 4, requires = "tile_palette")]
+    tile_colors: usize,
+    /// How automatically generated palettes represent each group of similar
+    /// colors.
+    #[arg(long, default_value = "mean")]
+    palette_representative: PaletteRepresentative,
+    /// Print the mean and maximum difference between the original and the
+    /// dithered pixels, measured with --difference.
+    #[arg(long)]
+    stats: bool,
+    /// Instead of dithering, print how the pixel at X,Y is matched against the
+    /// palette.
+    #[arg(long, conflicts_with = "tile_palette")]
+    probe: Option<PixelPos>,
+    /// Print the reports of --probe and --stats as json.
+    ///
+    /// The report of --stats is still written to stderr, so it doesn't mix
+    /// with an image written to stdout.
+    #[arg(long)]
+    json: bool,
+    /// Experimental: Match each pixel with the farthest instead of the nearest
+    /// palette color, producing false-color output.
+    #[arg(long)]
+    invert_palette_match: bool,
+    /// Diffuse only the error of these channels of the color space, e.g. 0 for
+    /// the lightness in cielab or oklab.
+    ///
+    /// Defaults to all channels.
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..=2))]
+    diffuse_channels: Vec<u8>,
+    /// Merge palette colors whose CIEDE2000 difference is below this threshold
+    /// into their average.
+    #[arg(long)]
+    merge_similar: Option<f32>,
+    /// Keep palette colors that are given more than once instead of removing
+    /// the repetitions.
+    #[arg(long, alias = "no-dedup")]
+    no_palette_dedup: bool,
+    /// Pass pixels of this color through unchanged, e.g. color keys.
+    ///
+    /// Locked pixels are excluded from error diffusion.
+    #[arg(long)]
+    lock_color: Vec<SrgbColor>,
+    /// Only dither where this mask image is white and pass all other pixels
+    /// through unchanged.
+    ///
+    /// The mask must have the same size as the image. Masked out pixels are
+    /// excluded from error diffusion.
+    #[arg(long)]
+    mask: Option<PathBuf>,
+    #[arg(skip)]
+    mask_image: Option<GrayImage>,
+    /// The loaded image before it was reduced to 8 bits, see --depth.
+    #[arg(skip)]
+    source: Option<Rgba32FImage>,
+    #[arg(skip)]
+    verbosity: Verbosity,
+    /// Offset of the patterns of the random, bayer and halftone algorithms.
+    #[arg(skip)]
+    pattern_offset: [u32; 2],
+    /// Leave fully transparent pixels untouched and exclude them from error
+    /// diffusion.
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    skip_transparent: bool,
+    /// Scan every other row from right to left when diffusing errors.
+    #[arg(long)]
+    serpentine: bool,
+    /// Compute and diffuse errors in linear light instead of the color space.
+    ///
+    /// Keeps the brightness of dithered gradients correct, especially with
+    /// --color-space srgb.
+    #[arg(long)]
+    linear_diffusion: bool,
+    /// The corner error diffusion starts at.
+    ///
+    /// The error flows away from this corner, which can be matched to the
+    /// direction of the light in an image.
+    #[arg(long, default_value = "top-left")]
+    diffusion_origin: DitherDiffusionOrigin,
+    /// Dither only the oklab lightness and keep the hue and chroma of each
+    /// pixel, like a halftone over the original colors.
+    ///
+    /// Works best with a grayscale palette. The result is no longer limited to
+    /// the palette colors.
+    #[arg(long, conflicts_with = "lock_color")]
+    lightness_only: bool,
+    /// Clamp colors to the range of the color space after they receive
+    /// diffused error, reducing sparkles near high contrast edges.
+    #[arg(long)]
+    error_clamp: bool,
+    /// Scale the diffused error by this factor between 0 and 1.
+    ///
+    /// Lower values keep flat areas crisper, 0 matches the threshold algorithm.
+    #[arg(long, default_value_t = 1.0)]
+    diffusion_strength: f32,
+    /// Split two-color palettes at this luma between 0 and 1 instead of
+    /// matching the nearest color.
+    ///
+    /// Only used by the threshold algorithm.
+    #[arg(long)]
+    threshold: Option<f32>,
+    /// Seed for the random algorithm and the position of the matrix of the
+    /// bayer, checkerboard and halftone algorithms.
+    ///
+    /// The same seed reproduces the same output with the same version.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Seed the random algorithm from system entropy and print the seed.
+    #[arg(long, conflicts_with = "seed")]
+    random_seed: bool,
+    /// Derive the noise of the random algorithm from each pixel's coordinates,
+    /// so tiles and crops get the same noise as the whole image.
+    #[arg(long)]
+    coordinate_noise: bool,
+    /// Move the pattern of the random, bayer and halftone algorithms with every
+    /// frame of an animated gif, for a lively grain instead of a static one.
+    #[arg(long)]
+    animate_pattern: bool,
+    /// Radius of extra noise the random algorithm adds in the color space,
+    /// either for all channels or per channel as R1,R2,R3.
+    #[arg(long, default_value = "0")]
+    noise: NoiseRadius,
+    /// How to treat the alpha channel.
+    #[arg(long, default_value = "preserve")]
+    alpha_mode: DitherAlpha,
+    /// Pixels with at least this alpha become opaque with --alpha-mode
+    /// threshold.
+    #[arg(long, default_value_t = 128)]
+    alpha_cutoff: u8,
+    /// Output magenta instead of palette colors outside the srgb gamut.
+    #[arg(long)]
+    flag_out_of_gamut: bool,
+    /// Don't warn about poor combinations of color space and difference.
+    #[arg(long)]
+    no_combination_warning: bool,
+}
+
+/// The report of --probe.
+#[derive(Serialize)]
+struct ProbeReport {
+    x: u32,
+    y: u32,
+    srgb: SrgbColor,
+    working: [f32; 3],
+    palette: Vec<ProbeCandidate>,
+}
+
+#[derive(Serialize)]
+struct ProbeCandidate {
+    srgb: SrgbColor,
+    working: [f32; 3],
+    difference: f32,
+    chosen: bool,
+}
+
+/// The report of --stats.
+#[derive(Serialize)]
+struct StatsReport {
+    difference: String,
+    mean: f32,
+    max: f32,
+}
+
+/// Format a report as json.
+///
+/// Non-finite numbers become null.
+fn to_json(report: &impl Serialize) -> String {
+    serde_json::to_string(report).expect("reports always serialize")
+}
+
+impl DitherCmd {
+    /// Resolve everything that must be the same for every image the command
+    /// runs on.
+    fn prepare(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+        if !(0.0..=1.0).contains(&self.diffusion_strength) {
+            exit_with_error("diffusion strength must be between 0 and 1");
+        }
+        if self.threshold.is_some_and(|t| !(0.0..=1.0).contains(&t)) {
+            exit_with_error("threshold must be between 0 and 1");
+        }
+        if self.json && self.probe.is_none() && !self.stats {
+            eprintln!("Warning: --json is ignored without --probe or --stats");
+        }
+        if let Some(Weights(weights)) = self.channel_weights {
+            if !weights.iter().all(|w| w.is_finite() && *w >= 0.0) {
+                exit_with_error("channel weights must be finite and must not be negative");
+            }
+        }
+        if let Some(Weights(weights)) = self.de00_weights {
+            if !weights.iter().all(|w| *w > 0.0 && w.is_finite()) {
+                exit_with_error("ciede2000 weights must be positive and finite");
+            }
+        }
+        if let Some(path) = &self.mask {
+            if verbosity.progress() {
+                eprintln!("Loading mask from {}", path.display());
+            }
+            let mask = ImageReader::open(path)
+                .map_err(ImageError::from)
+                .and_then(|reader| reader.decode())
+                .unwrap_or_else(|e| exit_with_error(e));
+            self.mask_image = Some(mask.into_luma8());
+        }
+        if self.random_seed {
+            self.seed = rand::random();
+            if verbosity.progress() {
+                eprintln!("Using seed {}", self.seed);
+            }
+        }
+        if let Some(path) = &self.palette_file {
+            let colors = load_palette_file(path, verbosity);
+            self.palette.extend(colors.into_iter().map(SrgbColor));
+        }
+        if let Some(path) = &self.palette_from {
+            let image =
+                load_image(Some(path), None, verbosity).unwrap_or_else(|e| exit_with_error(e));
+            let colors = unique_colors(&image).unwrap_or_else(|| {
+                exit_with_error(format!(
+                    "{} has more than {PALETTE_FROM_MAX_COLORS} colors",
+                    path.display()
+                ))
+            });
+            self.palette.extend(colors.into_iter().map(SrgbColor));
+        }
+        if let Some(builtin) = self.builtin_palette {
+            self.palette
+                .extend(builtin.colors().iter().copied().map(SrgbColor));
+        }
+    }
+
+    fn warn_about_combination(&self) {
+        if self.no_combination_warning {
+            return;
+        }
+        if let Some(warning) = combination_warning(self.color_space, self.difference) {
+            eprintln!("Warning: {warning}");
+        }
+    }
+
+    fn warn_about_threshold(&self, palette_len: usize) {
+        if self.threshold.is_none() {
+            return;
+        }
+        if !matches!(self.algorithm, DitherAlgorithm::Threshold) {
+            eprintln!("Warning: --threshold is ignored by algorithms other than threshold");
+        } else if palette_len != 2 {
+            eprintln!("Warning: --threshold is ignored for palettes without exactly two colors");
+        }
+    }
+
+    /// How many rows dithering an image takes, counting the rows of each
+    /// tile's region separately.
+    fn rows(&self, image: &RgbaImage) -> u64 {
+        let Some(Size { width, height }) = self.tile_palette else {
+            return image.height().into();
+        };
+        let columns = image.width().div_ceil(width);
+        let rows = (0..image.height())
+            .step_by(height as usize)
+            .map(|y| u64::from(tile_region(y, height, image.height()).1))
+            .sum::<u64>();
+        rows * u64::from(columns)
+    }
+
+    fn run(mut self, mut image: RgbaImage, control: &Control) -> Option<RgbaImage> {
+        if let Some(mask) = &self.mask_image {
+            if mask.dimensions() != image.dimensions() {
+                let (mw, mh) = mask.dimensions();
+                let (iw, ih) = image.dimensions();
+                exit_with_error(format!("the mask is {mw}x{mh} but the image is {iw}x{ih}"));
+            }
+        }
+
+        let original = self.lightness_only.then(|| image.clone());
+        if self.lightness_only {
+            dither::to_lightness(&mut image);
+            if let Some(source) = &mut self.source {
+                dither::to_lightness_f32(source);
+            }
+        }
+
+        let choice = self.choice();
+        let leaf = DitherLeaf {
+            cmd: self,
+            image,
+            control,
+        };
+        let mut image = dither::dispatch(choice, leaf)?;
+        if let Some(original) = original {
+            dither::restore_chroma(&mut image, &original);
+        }
+        Some(image)
+    }
+
+    /// The algorithm, color space and difference to dispatch on.
+    fn choice(&self) -> dither::Choice {
+        let algorithm = match self.algorithm {
+            DitherAlgorithm::Threshold => AlgorithmKind::Threshold,
+            DitherAlgorithm::Random => AlgorithmKind::Random,
+            DitherAlgorithm::FloydSteinberg => AlgorithmKind::FloydSteinberg,
+            DitherAlgorithm::FloydSteinberg1D => AlgorithmKind::FloydSteinberg1D,
+            DitherAlgorithm::Stucki => AlgorithmKind::Stucki,
+            DitherAlgorithm::Jarvis => AlgorithmKind::Jarvis,
+            DitherAlgorithm::Burkes => AlgorithmKind::Burkes,
+            DitherAlgorithm::Sierra => AlgorithmKind::Sierra,
+            DitherAlgorithm::SierraTwoRow => AlgorithmKind::SierraTwoRow,
+            DitherAlgorithm::SierraLite => AlgorithmKind::SierraLite,
+            DitherAlgorithm::Atkinson => AlgorithmKind::Atkinson,
+            DitherAlgorithm::Bayer => match self.matrix_size {
+                MatrixSize::Two => AlgorithmKind::Bayer2,
+                MatrixSize::Four => AlgorithmKind::Bayer4,
+                MatrixSize::Eight => AlgorithmKind::Bayer8,
+            },
+            DitherAlgorithm::Checkerboard => AlgorithmKind::Bayer2,
+            DitherAlgorithm::Halftone => AlgorithmKind::Halftone,
+        };
+        let color_space = match self.color_space {
+            DitherColorSpace::Srgb => ColorSpace::Srgb,
+            DitherColorSpace::LinSrgb => ColorSpace::LinSrgb,
+            DitherColorSpace::Cielab => ColorSpace::Cielab,
+            DitherColorSpace::Cieluv => ColorSpace::Cieluv,
+            DitherColorSpace::Cielch => ColorSpace::Cielch,
+            DitherColorSpace::Oklab => ColorSpace::Oklab,
+            DitherColorSpace::Okhsl => ColorSpace::Okhsl,
+            DitherColorSpace::Okhsv => ColorSpace::Okhsv,
+        };
+        let DiffCie94 { kl, k1, k2 } = if self.cie94_textiles {
+            DiffCie94::TEXTILES
+        } else {
+            DiffCie94::GRAPHIC_ARTS
+        };
+        let CmcRatio(DiffCmc { l, c }) = self.cmc_ratio;
+        let ciede2000 = match self.de00_weights {
+            Some(Weights([kl, kc, kh])) => DifferenceKind::Ciede2000Weighted { kl, kc, kh },
+            None => DifferenceKind::Ciede2000,
+        };
+        let weights = self.channel_weights.map(|w| w.0).unwrap_or([1.0; 3]);
+        use DitherDifference::*;
+        let (difference, clamp) = match self.difference {
+            Euclid => (DifferenceKind::Euclid, false),
+            EuclidClamp => (DifferenceKind::Euclid, true),
+            HyAb => (DifferenceKind::HyAb, false),
+            HyAbClamp => (DifferenceKind::HyAb, true),
+            Ciede2000 => (ciede2000, false),
+            Ciede2000Clamp => (ciede2000, true),
+            Cie76 => (DifferenceKind::Cie76, false),
+            Cie76Clamp => (DifferenceKind::Cie76, true),
+            Cie94 => (DifferenceKind::Cie94 { kl, k1, k2 }, false),
+            Cie94Clamp => (DifferenceKind::Cie94 { kl, k1, k2 }, true),
+            Oklab => (DifferenceKind::Oklab, false),
+            OklabClamp => (DifferenceKind::Oklab, true),
+            Cmc => (DifferenceKind::Cmc { l, c }, false),
+            CmcClamp => (DifferenceKind::Cmc { l, c }, true),
+            Redmean => (DifferenceKind::Redmean, false),
+            RedmeanClamp => (DifferenceKind::Redmean, true),
+            Manhattan => (DifferenceKind::Manhattan, false),
+            ManhattanClamp => (DifferenceKind::Manhattan, true),
+            WeightedEuclid => (DifferenceKind::WeightedEuclid(weights), false),
+            WeightedEuclidClamp => (DifferenceKind::WeightedEuclid(weights), true),
+        };
+        dither::Choice {
+            algorithm,
+            color_space,
+            difference,
+            clamp,
+        }
+    }
+
+    fn probe_cd<C, D>(self, image: &RgbaImage, difference: &D, pos: PixelPos)
+    where
+        C: fmt::Debug,
+        C: AsMut<[f32; 3]>,
+        C: AsRef<[f32; 3]>,
+        C: Copy,
+        C: HueChannel,
+        C: IntoColor<Lab>,
+        C: IntoColor<Srgb>,
+        D: Difference<C>,
+        Srgb: IntoColor<C>,
+    {
+        let Some(pixel) = image.get_pixel_checked(pos.x, pos.y) else {
+            let (width, height) = image.dimensions();
+            exit_with_error(format!(
+                "the probed pixel {},{} is outside the {width}x{height} image",
+                pos.x, pos.y
+            ));
+        };
+        let [r, g, b, _] = pixel.0;
+        let srgb = Srgb::new(r, g, b);
+        let color: C = srgb.into_format().into_color();
+
+        let palette = self.to_palette::<C>(self.colors::<C>(image));
+        let differences = palette.differences(difference, color).collect::<Vec<_>>();
+        let candidates = differences.iter().enumerate();
+        let cmp =
+            |(_, (_, a)): &(usize, &(C, f32)), (_, (_, b)): &(usize, &(C, f32))| a.total_cmp(b);
+        let winner = if self.invert_palette_match {
+            candidates.max_by(cmp)
+        } else {
+            candidates.min_by(cmp)
+        }
+        .map(|(i, _)| i);
+
+        let to_srgb = |color: C| {
+            let srgb: Srgb = color.into_color();
+            SrgbColor(srgb.into_format())
+        };
+
+        if self.json {
+            let palette = (differences.iter().enumerate())
+                .map(|(i, (c, difference))| ProbeCandidate {
+                    srgb: to_srgb(*c),
+                    working: *c.as_ref(),
+                    difference: *difference,
+                    chosen: Some(i) == winner,
+                })
+                .collect();
+            let report = ProbeReport {
+                x: pos.x,
+                y: pos.y,
+                srgb: SrgbColor(srgb),
+                working: *color.as_ref(),
+                palette,
+            };
+            println!("{}", to_json(&report));
+            return;
+        }
+
+        println!("Pixel at {},{}", pos.x, pos.y);
+        println!("  srgb:    {}", SrgbColor(srgb));
+        println!("  working: {color:?}");
+        println!("Palette");
+        for (i, (color, diff)) in differences.into_iter().enumerate() {
+            let marker = if Some(i) == winner { '*' } else { ' ' };
+            let srgb = to_srgb(color);
+            println!("{marker} {srgb}  diff: {diff:<12}  working: {color:?}");
+        }
+    }
+
+    fn run_acd<A, C, D>(self, image: RgbaImage, control: &Control, difference: &D) -> RgbaImage
+    where
+        A: Algorithm<C, D>,
+        C: AsMut<[f32; 3]>,
+        C: AsRef<[f32; 3]>,
+        C: Copy,
+        C: HueChannel,
+        C: IntoColor<Lab>,
+        C: IntoColor<Srgb>,
+        Srgb: IntoColor<C>,
+    {
+        if let Some(tile_size) = self.tile_palette {
+            self.warn_about_threshold(self.tile_colors);
+            return self.run_acd_tiled::<A, C, D>(image, tile_size, control, difference);
+        }
+
+        let palette = self.to_palette(self.colors::<C>(&image));
+        self.warn_about_threshold(palette.colors().len());
+        A::run(image, &palette, difference, &self.to_options(), control)
+            .unwrap_or_else(|e| exit_with_error(e))
+    }
+
+    fn run_acd_tiled<A, C, D>(
+        &self,
+        mut image: RgbaImage,
+        tile_size: Size,
+        control: &Control,
+        difference: &D,
+    ) -> RgbaImage
+    where
+        A: Algorithm<C, D>,
+        C: AsMut<[f32; 3]>,
+        C: AsRef<[f32; 3]>,
+        C: Copy,
+        C: HueChannel,
+        C: IntoColor<Lab>,
+        Srgb: IntoColor<C>,
+    {
+        let Size { width, height } = tile_size;
+        let (image_width, image_height) = image.dimensions();
+        let columns = image_width.div_ceil(width);
+        let rows = image_height.div_ceil(height);
+
+        // Every pixel is taken from one of the tiles whose region covers it,
+        // chosen at random in proportion to the tiles' blending weights.
+        let mut rng = SmallRng::seed_from_u64(self.seed);
+        let mut owners = Vec::with_capacity(image.len() / 4);
+        for y in 0..image_height {
+            for x in 0..image_width {
+                let column = pick_tile(x, width, columns, rng.gen_range(0.0..1.0));
+                let row = pick_tile(y, height, rows, rng.gen_range(0.0..1.0));
+                owners.push(row * columns + column);
+            }
+        }
+
+        let options = self.to_options();
+        let [ox, oy] = self.pattern_offset;
+        let original = image.clone();
+        for row in 0..rows {
+            let (top, region_height) = tile_region(row * height, height, image_height);
+            for column in 0..columns {
+                let (left, region_width) = tile_region(column * width, width, image_width);
+                let region = imageops::crop_imm(&original, left, top, region_width, region_height)
+                    .to_image();
+                let colors = self.auto_palette(&region, self.tile_colors);
+                let palette = self.to_palette(colors);
+
+                let mask = self.mask_image.as_ref().map(|mask| {
+                    imageops::crop_imm(mask, left, top, region_width, region_height).to_image()
+                });
+                let source = self.source.as_ref().map(|source| {
+                    imageops::crop_imm(source, left, top, region_width, region_height).to_image()
+                });
+                let options = options
+                    .clone()
+                    .with_mask(mask)
+                    .with_source(source)
+                    .with_noise_offset([ox.wrapping_add(left), oy.wrapping_add(top)]);
+                let region = A::run(region, &palette, difference, &options, control)
+                    .unwrap_or_else(|e| exit_with_error(e));
+
+                let tile = row * columns + column;
+                for (x, y, pixel) in region.enumerate_pixels() {
+                    let (x, y) = (left + x, top + y);
+                    if owners[(y * image_width + x) as usize] == tile {
+                        image.put_pixel(x, y, *pixel);
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// The palette colors to dither an image against.
+    fn colors<C>(&self, image: &RgbaImage) -> Vec<Srgb<u8>>
+    where
+        C: AsMut<[f32; 3]>,
+        C: AsRef<[f32; 3]>,
+        C: Copy,
+        C: IntoColor<Srgb>,
+        Srgb: IntoColor<C>,
+    {
+        if let Some(size) = self.auto_palette {
+            self.auto_palette(image, size.for_image(image, self.verbosity))
+        } else if let Some(size) = self.auto_palette_kmeans {
+            let n = size.for_image(image, self.verbosity);
+            mark::palette::kmeans::<C>(image, n, KMEANS_MAX_ITERATIONS, self.seed)
+        } else {
+            self.palette.iter().map(|c| c.0).collect()
+        }
+    }
+
+    /// Generate one palette for several images combined, so they can all be
+    /// dithered against it.
+    fn shared_palette(&self, images: &[&RgbaImage], size: PaletteSize) -> Vec<Srgb<u8>> {
+        let all = stack_images(images);
+        self.auto_palette(&all, size.for_image(&all, self.verbosity))
+    }
+
+    fn auto_palette(&self, image: &RgbaImage, n: usize) -> Vec<Srgb<u8>> {
+        let representative = self.palette_representative.into();
+        mark::palette::median_cut_with_representative(image, n, representative)
+    }
+
+    fn to_options(&self) -> Options {
+        let mut channels = [self.diffuse_channels.is_empty(); 3];
+        for channel in &self.diffuse_channels {
+            channels[*channel as usize] = true;
+        }
+        Options::new()
+            .with_diffused_channels(channels)
+            .with_locked_colors(self.lock_color.iter().map(|c| c.0).collect())
+            .with_skip_transparent(self.skip_transparent)
+            .with_serpentine(self.serpentine)
+            .with_diffusion_origin(match self.diffusion_origin {
+                DitherDiffusionOrigin::TopLeft => DiffusionOrigin::TopLeft,
+                DitherDiffusionOrigin::TopRight => DiffusionOrigin::TopRight,
+                DitherDiffusionOrigin::BottomLeft => DiffusionOrigin::BottomLeft,
+                DitherDiffusionOrigin::BottomRight => DiffusionOrigin::BottomRight,
+            })
+            .with_linear_diffusion(self.linear_diffusion)
+            .with_error_clamp(self.error_clamp)
+            .with_diffusion_strength(self.diffusion_strength)
+            .with_threshold(self.threshold)
+            .with_mask(self.mask_image.clone())
+            .with_source(self.source.clone())
+            .with_seed(self.seed)
+            .with_coordinate_noise(self.coordinate_noise)
+            .with_noise_offset(self.pattern_offset)
+            .with_noise_radius(self.noise.0)
+            .with_cell_size(self.cell_size)
+            .with_alpha_mode(match self.alpha_mode {
+                DitherAlpha::Preserve => AlphaMode::Preserve,
+                DitherAlpha::Threshold => AlphaMode::Threshold(self.alpha_cutoff),
+                DitherAlpha::Dither => AlphaMode::Dither,
+            })
+    }
+
+    fn to_palette<C>(&self, colors: Vec<Srgb<u8>>) -> Palette<C>
+    where
+        C: AsMut<[f32; 3]>,
+        C: AsRef<[f32; 3]>,
+        C: Copy,
+        C: HueChannel,
+        C: IntoColor<Lab>,
+        Srgb: IntoColor<C>,
+    {
+        if colors.is_empty() {
+            exit_with_error(PaletteError::Empty);
+        }
+        let palette = if self.no_palette_dedup {
+            Palette::from_srgb(&colors)
+        } else {
+            let palette = Palette::deduplicated(&colors);
+            let removed = colors.len() - palette.colors().len();
+            // Only the given colors can be repeated by mistake.
+            if removed > 0 && !self.palette.is_empty() {
+                eprintln!("Warning: removed {removed} repeated palette colors");
+            }
+            palette
+        };
+        let mut palette = palette
+            .with_inverted_match(self.invert_palette_match)
+            .with_out_of_gamut_flag(self.flag_out_of_gamut);
+        if let Some(threshold) = self.merge_similar {
+            palette = palette.merge_similar(threshold);
+        }
+        palette
+    }
+}
+
+/// Runs a [`DitherCmd`] once [`dither::dispatch`] has chosen the types.
+struct DitherLeaf<'a> {
+    cmd: DitherCmd,
+    image: RgbaImage,
+    control: &'a Control,
+}
+
+impl Dispatch for DitherLeaf<'_> {
+    type Output = Option<RgbaImage>;
+
+    fn run<A, C, D>(self, difference: D) -> Option<RgbaImage>
+    where
+        A: Algorithm<C, D>,
+        C: DispatchColor,
+        D: Difference<C> + Sync,
+        Srgb: IntoColor<C>,
+    {
+        let Self {
+            cmd,
+            image,
+            control,
+        } = self;
+        if let Some(pos) = cmd.probe {
+            cmd.probe_cd(&image, &difference, pos);
+            return None;
+        }
+
+        let stats = cmd.stats.then(|| (image.clone(), cmd.difference, cmd.json));
+        let result = cmd.run_acd::<A, C, D>(image, control, &difference);
+        if let Some((original, kind, json)) = stats {
+            print_stats::<C, D>(&original, &result, &difference, kind, json);
+        }
+        Some(result)
+    }
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+/// Dither every frame of an animated gif.
+///
+/// The input and output are always treated as gif images. The dither command
+/// also dithers every frame when reading and writing gif files.
+struct GifCmd {
+    #[command(flatten)]
+    dither: DitherCmd,
+    /// Generate a palette with this amount of colors from all frames combined
+    /// and dither every frame against it.
+    ///
+    /// Using a separate palette for each frame would make the animation flicker.
+    /// With --shared-palette, the palette is generated from all frames of all
+    /// inputs instead.
+    #[arg(
+        long,
+        group = "palette_source",
+        conflicts_with_all = ["palette", "palette_file", "palette_from", "builtin_palette", "tile_palette", "auto_palette", "auto_palette_kmeans"]
+    )]
+    frame_palette: Option<usize>,
+}
+
+impl GifCmd {
+    fn run(mut self, frames: Vec<Frame>, control: &Control) -> Option<Vec<Frame>> {
+        self.dither.warn_about_combination();
+
+        let shared_palette = match (self.frame_palette, self.dither.auto_palette.take()) {
+            (Some(n), _) => Some(PaletteSize::Colors(n)),
+            (None, size) => size,
+        };
+        if let Some(size) = shared_palette {
+            let buffers = frames.iter().map(Frame::buffer).collect::<Vec<_>>();
+            let colors = self.dither.shared_palette(&buffers, size);
+            self.dither.palette = colors.into_iter().map(SrgbColor).collect();
+        } else if self.dither.auto_palette_kmeans.is_some() || self.dither.tile_palette.is_some() {
+            eprintln!(
+                "Warning: palettes generated for each frame make the animation flicker, --auto-palette generates one for all frames"
+            );
+        }
+
+        let total = frames.len();
+        frames
+            .into_iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                if self.dither.verbosity.progress() {
+                    eprintln!("Dithering frame {}/{total}", i + 1);
+                }
+                let delay = frame.delay();
+                let mut dither = self.dither.clone();
+                if dither.animate_pattern {
+                    // Steps coprime to the power of two bayer matrix sizes
+                    // move the matrix to a new phase every frame.
+                    let i = i as u32;
+                    dither.seed = dither.seed.wrapping_add(i.into());
+                    dither.pattern_offset = [i.wrapping_mul(7), i.wrapping_mul(13)];
+                }
+                let image = dither.run(frame.into_buffer(), control)?;
+                Some(Frame::from_parts(image, 0, 0, delay))
+            })
+            .collect()
+    }
+}
+
+/// Print how much a dithered image differs from the original, see --stats.
+fn print_stats<C, D>(
+    original: &RgbaImage,
+    result: &RgbaImage,
+    difference: &D,
+    kind: DitherDifference,
+    json: bool,
+) where
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    let stats = compare::difference_stats::<C, D>(original, result, difference);
+    let name = kind.to_possible_value().expect("no variant is skipped");
+    if json {
+        let report = StatsReport {
+            difference: name.get_name().to_string(),
+            mean: stats.mean,
+            max: stats.max,
+        };
+        eprintln!("{}", to_json(&report));
+        return;
+    }
+    eprintln!(
+        "Difference to the original with {}: mean {:.3}, max {:.3}",
+        name.get_name(),
+        stats.mean,
+        stats.max
+    );
+    if kind.is_cielab_based() {
+        eprintln!("  in delta E, where differences around 1 are barely noticeable");
+    } else if kind.is_oklab_based() {
+        eprintln!("  in oklab units, where differences around 0.02 are barely noticeable");
+    } else {
+        eprintln!("  in units of the color space channels");
+    }
+}
+
+/// The region of a --tile-palette tile along one axis, as its start and
+/// length.
+///
+/// The region reaches half a tile into the neighbouring tiles, which is as far
+/// as the tile's blending weight is positive.
+fn tile_region(start: u32, size: u32, extent: u32) -> (u32, u32) {
+    let half = size / 2;
+    let from = start.saturating_sub(half);
+    let to = start.saturating_add(size).saturating_add(half).min(extent);
+    (from, to - from)
+}
+
+/// Pick the --tile-palette tile a pixel is taken from along one axis.
+///
+/// The blending weight of a tile falls off linearly from 1 at its center to 0
+/// at the centers of its neighbours, so the palettes of neighbouring tiles
+/// blend smoothly into each other. `choice` is a random number in `0..1`.
+fn pick_tile(pos: u32, size: u32, tiles: u32, choice: f32) -> u32 {
+    let own = pos / size;
+    let candidates = own.saturating_sub(1)..(own + 2).min(tiles);
+    let weight = |tile: u32| {
+        let center = tile as f32 * size as f32 + size as f32 / 2.0;
+        (1.0 - (pos as f32 + 0.5 - center).abs() / size as f32).max(0.0)
+    };
+    let total = candidates.clone().map(weight).sum::<f32>();
+    let mut choice = choice * total;
+    for tile in candidates {
+        choice -= weight(tile);
+        if choice < 0.0 {
+            return tile;
+        }
+    }
+    own
+}
+
+/// Stack images vertically into a single image.
+fn stack_images(images: &[&RgbaImage]) -> RgbaImage {
+    let width = images.iter().map(|i| i.width()).max().unwrap_or(0);
+    let height = images.iter().map(|i| i.height()).sum();
+    let mut stacked = RgbaImage::new(width, height);
+    let mut y = 0;
+    for image in images {
+        imageops::replace(&mut stacked, *image, 0, y);
+        y += i64::from(image.height());
+    }
+    stacked
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+/// Dither an image with several algorithms and tile the results into a grid.
+///
+/// The cells are not labeled. They are ordered left to right and top to bottom,
+/// starting with --algorithm followed by --compare.
+struct ContactCmd {
+    #[command(flatten)]
+    dither: DitherCmd,
+    /// Further algorithms to compare against --algorithm, separated by commas.
+    #[arg(long, required = true, value_delimiter = ',')]
+    compare: Vec<DitherAlgorithm>,
+    /// Number of cells per row, defaults to a roughly square grid.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    columns: Option<u32>,
+}
+
+impl ContactCmd {
+    fn run(self, image: RgbaImage, control: &Control) -> Option<RgbaImage> {
+        self.dither.warn_about_combination();
+
+        let mut algorithms = vec![self.dither.algorithm];
+        algorithms.extend(self.compare);
+        let cells = algorithms.len() as u32;
+        let columns = self
+            .columns
+            .unwrap_or_else(|| (cells as f32).sqrt().ceil() as u32)
+            .min(cells);
+        let rows = cells.div_ceil(columns);
+
+        let (width, height) = image.dimensions();
+        let (Some(sheet_width), Some(sheet_height)) =
+            (width.checked_mul(columns), height.checked_mul(rows))
+        else {
+            exit_with_error(format!(
+                "a {columns}x{rows} contact sheet of the {width}x{height} image is too large"
+            ));
+        };
+        let mut sheet = RgbaImage::new(sheet_width, sheet_height);
+        for (i, algorithm) in (0..).zip(algorithms) {
+            let name = algorithm
+                .to_possible_value()
+                .expect("no variant is skipped");
+            if self.dither.verbosity.progress() {
+                eprintln!("Dithering cell {}/{cells} with {}", i + 1, name.get_name());
+            }
+            let mut dither = self.dither.clone();
+            dither.algorithm = algorithm;
+            let cell = dither.run(image.clone(), control)?;
+            let x = i % columns * width;
+            let y = i / columns * height;
+            imageops::replace(&mut sheet, &cell, x.into(), y.into());
+        }
+        Some(sheet)
+    }
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+enum Cmd {
+    Bw(BwCmd),
+    Dither(DitherCmd),
+    Gif(GifCmd),
+    Thermal(ThermalCmd),
+    Info(InfoCmd),
+    Posterize(PosterizeCmd),
+    Invert(InvertCmd),
+    Contact(ContactCmd),
+}
+
+impl Cmd {
+    fn prepare(&mut self, verbosity: Verbosity) {
+        match self {
+            Self::Dither(cmd) => cmd.prepare(verbosity),
+            Self::Gif(cmd) => cmd.dither.prepare(verbosity),
+            Self::Contact(cmd) => cmd.dither.prepare(verbosity),
+            Self::Bw(_)
+            | Self::Thermal(_)
+            | Self::Info(_)
+            | Self::Posterize(_)
+            | Self::Invert(_) => {}
+        }
+    }
+
+    /// The palette colors explicitly given to the command, in order.
+    fn given_palette(&self) -> Vec<Srgb<u8>> {
+        match self {
+            Self::Bw(_) | Self::Info(_) | Self::Posterize(_) | Self::Invert(_) => vec![],
+            Self::Dither(cmd) => cmd.palette.iter().map(|c| c.0).collect(),
+            Self::Gif(cmd) => cmd.dither.palette.iter().map(|c| c.0).collect(),
+            Self::Contact(cmd) => cmd.dither.palette.iter().map(|c| c.0).collect(),
+            Self::Thermal(_) => vec![Srgb::new(0, 0, 0), Srgb::new(255, 255, 255)],
+        }
+    }
+
+    /// The dither options of commands that can dither a more precise source
+    /// image, see --depth.
+    fn precise_dither_mut(&mut self) -> Option<&mut DitherCmd> {
+        match self {
+            Self::Dither(cmd) => Some(cmd),
+            Self::Contact(cmd) => Some(&mut cmd.dither),
+            _ => None,
+        }
+    }
+
+    /// The dither options of commands that dither.
+    fn dither_mut(&mut self) -> Option<&mut DitherCmd> {
+        match self {
+            Self::Gif(cmd) => Some(&mut cmd.dither),
+            _ => self.precise_dither_mut(),
+        }
+    }
+
+    fn run(self, image: RgbaImage, control: &Control) -> Option<RgbaImage> {
+        match self {
+            Self::Bw(cmd) => Some(cmd.run(image)),
+            Self::Dither(cmd) => {
+                cmd.warn_about_combination();
+                cmd.run(image, control)
+            }
+            Self::Gif(_) => unreachable!("gif command is handled separately"),
+            Self::Thermal(cmd) => Some(cmd.run(image, control)),
+            Self::Info(_) => None,
+            Self::Posterize(cmd) => Some(cmd.run(image)),
+            Self::Invert(cmd) => Some(cmd.run(image)),
+            Self::Contact(cmd) => cmd.run(image, control),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Alpha {
+    /// Color channels are independent of the alpha channel.
+    Straight,
+    /// Color channels have already been multiplied by the alpha channel.
+    Premultiplied,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl From<PngCompression> for CompressionType {
+    fn from(value: PngCompression) -> Self {
+        match value {
+            PngCompression::Fast => Self::Fast,
+            PngCompression::Default => Self::Default,
+            PngCompression::Best => Self::Best,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Webp,
+    Gif,
+    Tiff,
+}
+
+impl From<OutputFormat> for ImageFormat {
+    fn from(value: OutputFormat) -> Self {
+        match value {
+            OutputFormat::Png => Self::Png,
+            OutputFormat::Jpeg => Self::Jpeg,
+            OutputFormat::Bmp => Self::Bmp,
+            OutputFormat::Webp => Self::WebP,
+            OutputFormat::Gif => Self::Gif,
+            OutputFormat::Tiff => Self::Tiff,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum InputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Webp,
+    Gif,
+    Tiff,
+    /// Any of pbm, pgm, ppm and pam.
+    Pnm,
+    Tga,
+    Ico,
+    Qoi,
+    Farbfeld,
+    Hdr,
+    Exr,
+}
+
+impl From<InputFormat> for ImageFormat {
+    fn from(value: InputFormat) -> Self {
+        match value {
+            InputFormat::Png => Self::Png,
+            InputFormat::Jpeg => Self::Jpeg,
+            InputFormat::Bmp => Self::Bmp,
+            InputFormat::Webp => Self::WebP,
+            InputFormat::Gif => Self::Gif,
+            InputFormat::Tiff => Self::Tiff,
+            InputFormat::Pnm => Self::Pnm,
+            InputFormat::Tga => Self::Tga,
+            InputFormat::Ico => Self::Ico,
+            InputFormat::Qoi => Self::Qoi,
+            InputFormat::Farbfeld => Self::Farbfeld,
+            InputFormat::Hdr => Self::Hdr,
+            InputFormat::Exr => Self::OpenExr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ResizeFilter {
+    /// Keeps hard edges, for example when upscaling a dithered image.
+    Nearest,
+    Triangle,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(value: ResizeFilter) -> Self {
+        match value {
+            ResizeFilter::Nearest => Self::Nearest,
+            ResizeFilter::Triangle => Self::Triangle,
+            ResizeFilter::Lanczos3 => Self::Lanczos3,
+        }
+    }
+}
+
+/// Adjustments applied to each image before the command runs.
+///
+/// They are applied in the order alpha, resize, brightness and contrast, gamma.
+#[derive(Debug, clap::Args)]
+struct Preprocess {
+    /// How to interpret the alpha channel of the loaded image.
+    #[arg(long, default_value = "straight")]
+    alpha: Alpha,
+
+    /// Composite the image over this opaque hex color in linear light, so
+    /// semi-transparent pixels are processed as they would be displayed.
+    #[arg(long)]
+    background: Option<SrgbColor>,
+
+    /// Resize image to WxH before processing.
+    ///
+    /// Resizing happens in linear light unless --resize-srgb is specified.
+    #[arg(long, group = "resize_or_scale")]
+    resize: Option<Size>,
+
+    /// Scale the image by this factor before processing.
+    ///
+    /// Like --resize, but keeps the aspect ratio.
+    #[arg(long, group = "resize_or_scale")]
+    scale: Option<f32>,
+
+    /// Downscale the image so its largest side is at most this many pixels,
+    /// for quickly trying out parameters on large images.
+    ///
+    /// Applied after --resize and --scale. Smaller images are left as they are.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    preview: Option<u32>,
+
+    /// Filter used by --resize, --scale and --preview.
+    #[arg(long, default_value = "lanczos3")]
+    resize_filter: ResizeFilter,
+
+    /// Resize gamma-encoded srgb values instead of linear light.
+    #[arg(long, requires = "resize_or_scale")]
+    resize_srgb: bool,
+
+    /// Add this to each channel in linear light, after resizing.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    brightness: f32,
+
+    /// Scale the distance of each channel from 0.5 in linear light by this
+    /// factor, after resizing.
+    #[arg(long, default_value_t = 1.0, allow_negative_numbers = true)]
+    contrast: f32,
+
+    /// Raise each gamma-encoded srgb channel to the power of 1/GAMMA, after
+    /// adjusting brightness and contrast.
+    ///
+    /// Values above 1 brighten and values below 1 darken the midtones.
+    #[arg(long, default_value_t = 1.0)]
+    gamma: f32,
+}
+
+impl Preprocess {
+    /// The size to resize an image to, if any.
+    fn size(&self, dimensions: (u32, u32)) -> Option<Size> {
+        let size = self.resized_size(dimensions);
+        let Some(max) = self.preview else {
+            return size;
+        };
+        let (width, height) = size.map_or(dimensions, |s| (s.width, s.height));
+        let largest = width.max(height);
+        if largest <= max {
+            return size;
+        }
+        Some(scaled_size((width, height), max as f32 / largest as f32))
+    }
+
+    /// The size given by --resize or --scale, if any.
+    fn resized_size(&self, dimensions: (u32, u32)) -> Option<Size> {
+        if let Some(size) = self.resize {
+            return Some(size);
+        }
+        let scale = self.scale?;
+        if scale.is_nan() || scale <= 0.0 {
+            exit_with_error("scale must be positive");
+        }
+        Some(scaled_size(dimensions, scale))
+    }
+
+    fn run(&self, mut image: RgbaImage) -> RgbaImage {
+        normalize_alpha(&mut image, self.alpha);
+        if let Some(SrgbColor(background)) = self.background {
+            adjust::composite_over(&mut image, background.into_format());
+        }
+        if let Some(Size { width, height }) = self.size(image.dimensions()) {
+            let linear = !self.resize_srgb;
+            let filter = self.resize_filter.into();
+            image = resize::resize(&image, width, height, filter, linear);
+        }
+        if self.brightness != 0.0 || self.contrast != 1.0 {
+            adjust::brightness_contrast(&mut image, self.brightness, self.contrast);
+        }
+        if self.gamma.is_nan() || self.gamma <= 0.0 {
+            exit_with_error("gamma must be positive");
+        }
+        if self.gamma != 1.0 {
+            adjust::gamma(&mut image, self.gamma);
+        }
+        image
+    }
+
+    /// Like [`Self::run`], but for the image kept by --depth 16.
+    ///
+    /// Only resizing is supported so far.
+    fn run_f32(&self, mut image: Rgba32FImage) -> Rgba32FImage {
+        let adjusts_colors = !matches!(self.alpha, Alpha::Straight)
+            || self.background.is_some()
+            || self.brightness != 0.0
+            || self.contrast != 1.0
+            || self.gamma != 1.0;
+        if adjusts_colors {
+            exit_with_error(
+                "--depth 16 can't be combined with --alpha, --background, --brightness, --contrast or --gamma",
+            );
+        }
+        if let Some(Size { width, height }) = self.size(image.dimensions()) {
+            let linear = !self.resize_srgb;
+            let filter = self.resize_filter.into();
+            image = resize::resize_f32(&image, width, height, filter, linear);
+        }
+        image
+    }
+}
+
+/// Scale both sides of an image, keeping them at least one pixel long.
+fn scaled_size((width, height): (u32, u32), scale: f32) -> Size {
+    let scaled = |n: u32| ((n as f32 * scale).round() as u32).max(1);
+    Size {
+        width: scaled(width),
+        height: scaled(height),
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum PaletteOrder {
+    /// Order of the palette colors given to the command, if any.
+    AsGiven,
+    /// From darkest to brightest.
+    Luminance,
+    /// From most to least used.
+    Usage,
+}
+
+/// Options for writing the resulting image.
+#[derive(Debug, clap::Args)]
+struct Output {
+    /// Format of the written image.
+    ///
+    /// Defaults to the format matching the file extension, or png when
+    /// writing to stdout.
+    #[arg(long)]
+    format: Option<OutputFormat>,
+
+    /// Quality of jpeg images from 1 to 100, defaults to 75.
+    ///
+    /// Other formats are written losslessly.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+
+    /// Upscale the result by this integer factor, turning each pixel into a
+    /// block of NxN pixels.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pixel_scale: u32,
+
+    /// Compression level when writing png images.
+    #[arg(long, default_value = "default")]
+    png_compression: PngCompression,
+
+    /// Write an indexed png image.
+    ///
+    /// Fails if the image contains more than 256 distinct colors.
+    #[arg(long)]
+    indexed: bool,
+
+    /// Order of the colors in the index table of an indexed image.
+    ///
+    /// Colors not found in the given palette are placed after the given colors
+    /// in the order they first appear in the image.
+    #[arg(long, default_value = "as-given", requires = "indexed")]
+    palette_order: PaletteOrder,
+
+    /// Also split the indexed image into one 1-bit png per bit of the palette
+    /// index and write them to this directory.
+    ///
+    /// The file plane0.png contains the least significant bit.
+    #[arg(long, requires = "indexed")]
+    bitplanes: Option<PathBuf>,
+}
+
+impl Output {
+    fn scale(&self, image: RgbaImage) -> RgbaImage {
+        if self.pixel_scale == 1 {
+            return image;
+        }
+        let (width, height) = image.dimensions();
+        let fits = |side: u32| side.checked_mul(self.pixel_scale).is_some();
+        if !fits(width) || !fits(height) {
+            exit_with_error(format!(
+                "--pixel-scale {} makes the {width}x{height} image too large",
+                self.pixel_scale
+            ));
+        }
+        resize::pixel_scale(&image, self.pixel_scale)
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+struct Args {
+    /// Load images from files instead of stdin.
+    ///
+    /// With the url feature enabled, http and https urls are fetched using curl.
+    ///
+    /// Can be given multiple times to process each image separately, in which
+    /// case --out must be a directory or a file name template.
     #[arg(long, short)]
-    r#in: Option<PathBuf>,
+    r#in: Vec<PathBuf>,
+
+    /// Decode the input images as this format.
+    ///
+    /// By default, the format is guessed from the file contents, or from the
+    /// file extension for local files.
+    #[arg(long)]
+    in_format: Option<InputFormat>,
 
     /// Output image to file instead of stdout.
+    ///
+    /// When processing multiple images, this is either a directory to write
+    /// the results to under their original file names, or a template like
+    /// out/{stem}_dithered.png where {stem} is replaced by the file name of
+    /// each input without its extension.
     #[arg(long, short)]
     out: Option<PathBuf>,
 
+    /// Bits per channel of the loaded image to dither.
+    ///
+    /// With 16, the dither and contact commands dither the full precision of
+    /// 16 bit images instead of reducing them to 8 bits when loading them.
+    #[arg(long, default_value = "8")]
+    depth: Depth,
+
+    #[command(flatten)]
+    preprocess: Preprocess,
+
+    /// Abort with an error if dithering takes longer than this many seconds.
+    #[arg(long)]
+    timeout: Option<Seconds>,
+
+    #[command(flatten)]
+    output: Output,
+
+    /// Also write an image showing the CIEDE2000 difference between the
+    /// original and the result to this file.
+    ///
+    /// Identical pixels are black, differences of 50 or more are white.
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+
+    /// Also write a second frame to this file so that quickly alternating
+    /// between both frames approximates the original better than either frame.
+    ///
+    /// The second frame is produced by running the command again on an image
+    /// that compensates for the error of the first frame.
+    #[arg(long)]
+    temporal: Option<PathBuf>,
+
+    /// Print a hash of the resulting pixels to stderr, for checking that a
+    /// run reproduces an earlier result.
+    ///
+    /// The hash is the 64 bit FNV-1a hash of the RGBA bytes of the result, row
+    /// by row and before --pixel-scale. For gifs, the bytes of all frames are
+    /// hashed in order.
+    #[arg(long)]
+    print_hash: bool,
+
+    /// Generate a single --auto-palette from all inputs combined and dither
+    /// every input against it, so the colors of related images match.
+    ///
+    /// For the gif command, --frame-palette may be used instead of
+    /// --auto-palette.
+    #[arg(long)]
+    shared_palette: bool,
+
+    /// Only print warnings and errors.
+    #[arg(long, short, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also print how long loading, processing and writing took.
+    #[arg(long, short)]
+    verbose: bool,
+
+    /// List the builtin palettes and exit.
+    #[arg(long, exclusive = true)]
+    list_palettes: bool,
+
     #[command(subcommand)]
-    cmd: Cmd,
+    cmd: Option<Cmd>,
+}
+
+impl Args {
+    fn in_format(&self) -> Option<ImageFormat> {
+        self.in_format.map(ImageFormat::from)
+    }
+
+    fn verbosity(&self) -> Verbosity {
+        match (self.quiet, self.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, true) => Verbosity::Verbose,
+            (false, false) => Verbosity::Normal,
+        }
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// The 64 bit FNV-1a hash of some bytes, continuing from a previous hash.
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+fn print_hash(images: &[&RgbaImage]) {
+    let hash = images
+        .iter()
+        .fold(FNV_OFFSET_BASIS, |hash, image| fnv1a(hash, image.as_raw()));
+    eprintln!("Hash: {hash:016x}");
+}
+
+fn as_url(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|p| p.starts_with("http://") || p.starts_with("https://"))
+}
+
+#[cfg(feature = "url")]
+fn fetch_url(url: &str) -> Vec<u8> {
+    let output = process::Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", url])
+        .output()
+        .unwrap_or_else(|e| exit_with_error(format!("failed to run curl: {e}")));
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        exit_with_error(format!("failed to fetch {url}: {}", stderr.trim()));
+    }
+    output.stdout
+}
+
+#[cfg(not(feature = "url"))]
+fn fetch_url(_url: &str) -> Vec<u8> {
+    exit_with_error("loading images from urls requires the url feature");
+}
+
+/// Decode an image, guessing its format from the contents unless a format is
+/// given.
+fn decode_image(buf: Vec<u8>, format: Option<ImageFormat>) -> ImageResult<DynamicImage> {
+    let mut reader = ImageReader::new(Cursor::new(buf));
+    match format {
+        Some(format) => reader.set_format(format),
+        None => reader = reader.with_guessed_format()?,
+    }
+    reader.decode()
 }
 
-fn load_image(r#in: &Option<PathBuf>) -> RgbaImage {
-    if let Some(path) = r#in {
-        eprintln!("Loading image from {}", path.display());
-        ImageReader::open(path)
-            .expect("failed to load image from file")
-            .decode()
-            .expect("failed to decode image data")
+fn load_image(
+    r#in: Option<&Path>,
+    format: Option<ImageFormat>,
+    verbosity: Verbosity,
+) -> ImageResult<RgbaImage> {
+    Ok(load_dynamic_image(r#in, format, verbosity)?.into_rgba8())
+}
+
+fn load_dynamic_image(
+    r#in: Option<&Path>,
+    format: Option<ImageFormat>,
+    verbosity: Verbosity,
+) -> ImageResult<DynamicImage> {
+    let image = if let Some(url) = r#in.and_then(as_url) {
+        if verbosity.progress() {
+            eprintln!("Loading image from {url}");
+        }
+        decode_image(fetch_url(url), format)?
+    } else if let Some(path) = r#in {
+        if verbosity.progress() {
+            eprintln!("Loading image from {}", path.display());
+        }
+        let mut reader = ImageReader::open(path)?;
+        if let Some(format) = format {
+            reader.set_format(format);
+        }
+        reader.decode()?
     } else {
-        eprintln!("Loading image from stdin");
+        if verbosity.progress() {
+            eprintln!("Loading image from stdin");
+        }
         let mut buf = vec![];
-        std::io::stdin()
-            .read_to_end(&mut buf)
-            .expect("failed to read stdin");
-        ImageReader::new(Cursor::new(buf))
-            .with_guessed_format()
-            .expect("failed to guess image format")
-            .decode()
-            .expect("failed to decode image data")
+        std::io::stdin().read_to_end(&mut buf)?;
+        decode_image(buf, format)?
+    };
+    Ok(image)
+}
+
+fn load_palette_file(path: &Path, verbosity: Verbosity) -> Vec<Srgb<u8>> {
+    if verbosity.progress() {
+        eprintln!("Loading palette from {}", path.display());
     }
-    .into_rgba8()
+    let bytes = std::fs::read(path).unwrap_or_else(|e| {
+        exit_with_error(format!("failed to load palette {}: {e}", path.display()))
+    });
+    if bytes.starts_with(ase::SIGNATURE) {
+        return ase::parse(&bytes).unwrap_or_else(|e| exit_with_error(e));
+    }
+
+    let text = String::from_utf8(bytes).unwrap_or_else(|e| exit_with_error(e));
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.parse::<SrgbColor>() {
+            Ok(color) => color.0,
+            Err(e) => exit_with_error(format!("invalid palette color {line:?}: {e}")),
+        })
+        .collect()
 }
 
-fn save_image(out: &Option<PathBuf>, image: RgbaImage) {
-    if let Some(path) = out {
-        eprintln!("Writing image to {}", path.display());
-        image.save(path).expect("failed to save image to file");
+/// The unique colors of the visible pixels of an image, in order of their
+/// first appearance, or `None` if there are too many.
+fn unique_colors(image: &RgbaImage) -> Option<Vec<Srgb<u8>>> {
+    let mut seen = HashSet::new();
+    let mut colors = vec![];
+    for pixel in image.pixels().filter(|p| p.0[3] > 0) {
+        let [r, g, b, _] = pixel.0;
+        if seen.insert([r, g, b]) {
+            if colors.len() == PALETTE_FROM_MAX_COLORS {
+                return None;
+            }
+            colors.push(Srgb::new(r, g, b));
+        }
+    }
+    Some(colors)
+}
+
+fn load_frames(r#in: Option<&Path>, verbosity: Verbosity) -> ImageResult<Vec<Frame>> {
+    let buf = if let Some(path) = r#in {
+        if verbosity.progress() {
+            eprintln!("Loading gif from {}", path.display());
+        }
+        std::fs::read(path)?
     } else {
-        eprintln!("Writing image to stdout");
+        if verbosity.progress() {
+            eprintln!("Loading gif from stdin");
+        }
         let mut buf = vec![];
-        image
-            .write_to(&mut Cursor::new(&mut buf), ImageFormat::Png)
-            .expect("failed to export image to bytes");
+        std::io::stdin().read_to_end(&mut buf)?;
+        buf
+    };
+    GifDecoder::new(Cursor::new(buf))?
+        .into_frames()
+        .collect_frames()
+}
+
+fn unpremultiply_image(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let unpremultiply = |c: u8| {
+            if a == 0 {
+                return 0;
+            }
+            let (c, a) = (c as u32, a as u32);
+            ((c * 255 + a / 2) / a).min(255) as u8
+        };
+        pixel.0 = [unpremultiply(r), unpremultiply(g), unpremultiply(b), a];
+    }
+}
+
+fn normalize_alpha(image: &mut RgbaImage, alpha: Alpha) {
+    match alpha {
+        Alpha::Straight => {}
+        Alpha::Premultiplied => unpremultiply_image(image),
+    }
+}
+
+fn write_png<W: Write>(
+    writer: W,
+    image: &RgbaImage,
+    compression: PngCompression,
+) -> ImageResult<()> {
+    let encoder = PngEncoder::new_with_quality(writer, compression.into(), PngFilterType::Adaptive);
+    image.write_with_encoder(encoder)
+}
+
+/// Sort the distinct colors of an image and assign each pixel its color's index.
+fn index_image(
+    image: &RgbaImage,
+    order: PaletteOrder,
+    given: &[Srgb<u8>],
+) -> (Vec<[u8; 4]>, Vec<u8>) {
+    let mut colors = vec![];
+    let mut usage = HashMap::<[u8; 4], usize>::new();
+    for pixel in image.pixels() {
+        let count = usage.entry(pixel.0).or_insert_with(|| {
+            colors.push(pixel.0);
+            0
+        });
+        *count += 1;
+    }
+    if colors.len() > 256 {
+        exit_with_error(format!(
+            "indexed images can have at most 256 colors, but this image has {}",
+            colors.len()
+        ));
+    }
+
+    match order {
+        PaletteOrder::AsGiven => colors.sort_by_key(|[r, g, b, _]| {
+            let position = given.iter().position(|c| *c == Srgb::new(*r, *g, *b));
+            position.unwrap_or(given.len())
+        }),
+        PaletteOrder::Luminance => colors.sort_by(|[r1, g1, b1, _], [r2, g2, b2, _]| {
+            let y1 = Xyz::from_color(Srgb::new(*r1, *g1, *b1).into_format::<f32>()).y;
+            let y2 = Xyz::from_color(Srgb::new(*r2, *g2, *b2).into_format::<f32>()).y;
+            y1.total_cmp(&y2)
+        }),
+        PaletteOrder::Usage => colors.sort_by_key(|c| Reverse(usage[c])),
+    }
+
+    let indices = colors
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (*c, i as u8))
+        .collect::<HashMap<_, _>>();
+    let data = image.pixels().map(|p| indices[&p.0]).collect();
+    (colors, data)
+}
+
+fn write_indexed_png<W: Write>(
+    writer: W,
+    image: &RgbaImage,
+    output: &Output,
+    given: &[Srgb<u8>],
+) -> Result<(), png::EncodingError> {
+    let (colors, indices) = index_image(image, output.palette_order, given);
+
+    let (depth, bits) = match colors.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+    let per_byte = 8 / bits;
+    let mut data = vec![];
+    for row in indices.chunks(image.width() as usize) {
+        for chunk in row.chunks(per_byte) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .map(|(i, index)| index << (8 - bits * (i + 1)))
+                .fold(0, |acc, index| acc | index);
+            data.push(byte);
+        }
+    }
+
+    let mut encoder = png::Encoder::new(writer, image.width(), image.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_compression(png_compression(output.png_compression));
+    encoder.set_palette(
+        colors
+            .iter()
+            .flat_map(|[r, g, b, _]| [*r, *g, *b])
+            .collect::<Vec<_>>(),
+    );
+    if colors.iter().any(|[_, _, _, a]| *a < 255) {
+        encoder.set_trns(colors.iter().map(|[_, _, _, a]| *a).collect::<Vec<_>>());
+    }
+    encoder.write_header()?.write_image_data(&data)
+}
+
+fn png_compression(compression: PngCompression) -> png::Compression {
+    match compression {
+        PngCompression::Fast => png::Compression::Fast,
+        PngCompression::Default => png::Compression::Default,
+        PngCompression::Best => png::Compression::Best,
+    }
+}
+
+/// Write a 1-bit grayscale png where set pixels are white.
+fn write_1bit_png<W: Write>(
+    writer: W,
+    width: u32,
+    height: u32,
+    pixels: impl Fn(u32, u32) -> bool,
+    compression: PngCompression,
+) -> Result<(), png::EncodingError> {
+    let mut data = vec![];
+    for y in 0..height {
+        let mut row = vec![0_u8; width.div_ceil(8) as usize];
+        for x in 0..width {
+            if pixels(x, y) {
+                row[x as usize / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        data.extend(row);
+    }
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+    encoder.set_compression(png_compression(compression));
+    encoder.write_header()?.write_image_data(&data)
+}
+
+fn save_bitplanes(
+    dir: &Path,
+    image: &RgbaImage,
+    output: &Output,
+    given: &[Srgb<u8>],
+    verbosity: Verbosity,
+) {
+    let image = output.scale(image.clone());
+    let (colors, data) = index_image(&image, output.palette_order, given);
+    let planes = (usize::BITS - (colors.len() - 1).leading_zeros()).max(1);
+    std::fs::create_dir_all(dir)
+        .unwrap_or_else(|e| exit_with_error(format!("failed to create {}: {e}", dir.display())));
+    for plane in 0..planes {
+        let path = dir.join(format!("plane{plane}.png"));
+        if verbosity.progress() {
+            eprintln!("Writing bitplane to {}", path.display());
+        }
+        let file = File::create(&path).unwrap_or_else(|e| {
+            exit_with_error(format!("failed to create {}: {e}", path.display()))
+        });
+        let index = |x, y| data[(y * image.width() + x) as usize];
+        write_1bit_png(
+            BufWriter::new(file),
+            image.width(),
+            image.height(),
+            |x, y| (index(x, y) >> plane) & 1 == 1,
+            output.png_compression,
+        )
+        .unwrap_or_else(|e| exit_with_error(format!("failed to write {}: {e}", path.display())));
+    }
+}
+
+/// Whether an image contains at most two distinct colors.
+fn is_two_color(image: &RgbaImage) -> bool {
+    let mut colors = vec![];
+    for pixel in image.pixels() {
+        if !colors.contains(pixel) {
+            if colors.len() == 2 {
+                return false;
+            }
+            colors.push(*pixel);
+        }
+    }
+    true
+}
+
+fn write_output_png<W: Write>(writer: W, image: &RgbaImage, output: &Output, given: &[Srgb<u8>]) {
+    // Two-color images fit into 1-bit indexed pngs, which are much smaller.
+    let two_color = is_two_color(image);
+    if output.indexed || two_color {
+        write_indexed_png(writer, image, output, given).expect("failed to write indexed png");
+    } else {
+        write_png(writer, image, output.png_compression).expect("failed to write png");
+    }
+}
+
+fn write_output<W: Write + Seek>(
+    mut writer: W,
+    image: RgbaImage,
+    format: ImageFormat,
+    output: &Output,
+    given: &[Srgb<u8>],
+) {
+    if format == ImageFormat::Png {
+        write_output_png(writer, &image, output, given);
+        return;
+    }
+
+    let image = DynamicImage::ImageRgba8(image);
+    let result = match format {
+        ImageFormat::Jpeg => {
+            // Jpeg images have no alpha channel.
+            let image = DynamicImage::from(image.into_rgb8());
+            let quality = output.quality.unwrap_or(75);
+            image.write_with_encoder(JpegEncoder::new_with_quality(writer, quality))
+        }
+        _ => image.write_to(&mut writer, format),
+    };
+    result.unwrap_or_else(|e| exit_with_error(e));
+}
+
+fn save_image(
+    out: Option<&Path>,
+    image: RgbaImage,
+    output: &Output,
+    given: &[Srgb<u8>],
+    verbosity: Verbosity,
+) {
+    let image = output.scale(image);
+    let format = match (output.format, out) {
+        (Some(format), _) => format.into(),
+        (None, Some(path)) => ImageFormat::from_path(path).unwrap_or_else(|e| exit_with_error(e)),
+        (None, None) => ImageFormat::Png,
+    };
+    if format != ImageFormat::Png && output.indexed {
+        exit_with_error("indexed output is only supported for png images");
+    }
+    if output.quality.is_some() && format != ImageFormat::Jpeg {
+        eprintln!("Warning: --quality is ignored, {format:?} images are written losslessly");
+    }
+    if !format.writing_enabled() {
+        exit_with_error(format!(
+            "writing {format:?} images is not supported by this build"
+        ));
+    }
+
+    if let Some(path) = out {
+        if verbosity.progress() {
+            eprintln!("Writing image to {}", path.display());
+        }
+        let file = File::create(path).expect("failed to create image file");
+        write_output(BufWriter::new(file), image, format, output, given);
+    } else {
+        if verbosity.progress() {
+            eprintln!("Writing image to stdout");
+        }
+        let mut buf = Cursor::new(vec![]);
+        write_output(&mut buf, image, format, output, given);
         std::io::stdout()
-            .write_all(&buf)
+            .write_all(buf.get_ref())
             .expect("failed to write image to stdout");
     }
 }
 
+fn save_frames(out: Option<&Path>, frames: Vec<Frame>, verbosity: Verbosity) {
+    let mut buf = vec![];
+    let mut encoder = GifEncoder::new(&mut buf);
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .expect("failed to configure gif encoder");
+    encoder
+        .encode_frames(frames)
+        .expect("failed to export gif to bytes");
+    drop(encoder);
+
+    if let Some(path) = out {
+        if verbosity.progress() {
+            eprintln!("Writing gif to {}", path.display());
+        }
+        std::fs::write(path, buf).expect("failed to save gif to file");
+    } else {
+        if verbosity.progress() {
+            eprintln!("Writing gif to stdout");
+        }
+        std::io::stdout()
+            .write_all(&buf)
+            .expect("failed to write gif to stdout");
+    }
+}
+
+fn exit_with_error(error: impl fmt::Display) -> ! {
+    eprintln!("Error: {error}");
+    process::exit(1);
+}
+
+/// Where to write the result of one of multiple inputs.
+fn batch_out_path(out: &Path, input: &Path) -> PathBuf {
+    let template = out.to_string_lossy();
+    if template.contains("{stem}") {
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        return PathBuf::from(template.replace("{stem}", &stem));
+    }
+    if !out.is_dir() {
+        exit_with_error(format!(
+            "{} is neither a directory nor a template containing {{stem}}",
+            out.display()
+        ));
+    }
+    out.join(input.file_name().unwrap_or_default())
+}
+
+/// Replace the --auto-palette or --frame-palette of the command with a palette
+/// generated from all inputs combined, see --shared-palette.
+fn share_palette(args: &Args, cmd: &mut Cmd) {
+    let frame_palette = match cmd {
+        Cmd::Gif(gif) => gif.frame_palette.map(PaletteSize::Colors),
+        _ => None,
+    };
+    let Some(dither) = cmd.dither_mut() else {
+        exit_with_error("--shared-palette only works with commands that dither");
+    };
+    let Some(size) = frame_palette.or(dither.auto_palette) else {
+        exit_with_error("--shared-palette requires --auto-palette or --frame-palette");
+    };
+    if args.r#in.len() < 2 {
+        return;
+    }
+
+    let verbosity = args.verbosity();
+    let mut images = vec![];
+    for path in &args.r#in {
+        let loaded = if is_gif_path(path) {
+            load_frames(Some(path), verbosity)
+                .map(|frames| frames.into_iter().map(Frame::into_buffer).collect())
+        } else {
+            load_image(Some(path), args.in_format(), verbosity).map(|image| vec![image])
+        };
+        let loaded = loaded
+            .unwrap_or_else(|e| exit_with_error(format!("failed to load {}: {e}", path.display())));
+        images.extend(loaded.into_iter().map(|image| args.preprocess.run(image)));
+    }
+
+    let colors = dither.shared_palette(&images.iter().collect::<Vec<_>>(), size);
+    dither.palette = colors.into_iter().map(SrgbColor).collect();
+    dither.auto_palette = None;
+    if let Cmd::Gif(gif) = cmd {
+        gif.frame_palette = None;
+    }
+}
+
+/// Whether a path is a local gif file, judging by its extension.
+fn is_gif_path(path: &Path) -> bool {
+    as_url(path).is_none()
+        && path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("gif"))
+}
+
+/// Whether the result is written as a gif image.
+fn writes_gif(output: &Output, out: Option<&Path>) -> bool {
+    match (output.format, out) {
+        (Some(format), _) => matches!(format, OutputFormat::Gif),
+        (None, Some(path)) => is_gif_path(path),
+        (None, None) => false,
+    }
+}
+
+fn process_frames(
+    args: &Args,
+    cmd: &GifCmd,
+    frames: Vec<Frame>,
+    out: Option<&Path>,
+    control: &Control,
+) {
+    let verbosity = args.verbosity();
+    let frames = frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let image = args.preprocess.run(frame.into_buffer());
+            Frame::from_parts(image, 0, 0, delay)
+        })
+        .collect();
+    let Some(frames) = verbosity.timed("Processing", || cmd.clone().run(frames, control)) else {
+        return;
+    };
+    if args.print_hash {
+        print_hash(&frames.iter().map(Frame::buffer).collect::<Vec<_>>());
+    }
+    let frames = frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let image = args.output.scale(frame.into_buffer());
+            Frame::from_parts(image, 0, 0, delay)
+        })
+        .collect();
+    verbosity.timed("Writing", || save_frames(out, frames, verbosity));
+}
+
+/// Exit if any outputs were requested that only exist for single images, since
+/// animated gifs are processed frame by frame.
+fn reject_single_image_outputs(args: &Args) {
+    if args.heatmap.is_some() || args.temporal.is_some() || args.output.bitplanes.is_some() {
+        exit_with_error("--heatmap, --temporal and --bitplanes can't be used with animated gifs");
+    }
+}
+
+/// Run the command on one input, failing only if the input can't be loaded.
+fn process(args: &Args, cmd: &Cmd, r#in: Option<&Path>, out: Option<&Path>) -> ImageResult<()> {
+    let mut control = Control::new();
+    if let Some(Seconds(timeout)) = args.timeout {
+        control = control.with_deadline(Instant::now() + timeout);
+    }
+
+    let verbosity = args.verbosity();
+    if let Cmd::Gif(cmd) = cmd {
+        reject_single_image_outputs(args);
+        let frames = verbosity.timed("Loading", || load_frames(r#in, verbosity))?;
+        process_frames(args, cmd, frames, out, &control);
+        return Ok(());
+    }
+
+    if let (Cmd::Dither(dither), Some(path)) = (cmd, r#in) {
+        if is_gif_path(path) && writes_gif(&args.output, out) {
+            let frames = verbosity.timed("Loading", || load_frames(r#in, verbosity))?;
+            if frames.len() > 1 {
+                reject_single_image_outputs(args);
+                let cmd = GifCmd {
+                    dither: dither.clone(),
+                    frame_palette: None,
+                };
+                process_frames(args, &cmd, frames, out, &control);
+                return Ok(());
+            }
+        }
+    }
+
+    if let Cmd::Info(cmd) = cmd {
+        cmd.clone()
+            .run(&load_dynamic_image(r#in, args.in_format(), verbosity)?);
+        return Ok(());
+    }
+
+    let mut main_cmd = cmd.clone();
+    let image = verbosity.timed("Loading", || match main_cmd.precise_dither_mut() {
+        Some(dither) if args.depth == Depth::Sixteen => {
+            let image = load_dynamic_image(r#in, args.in_format(), verbosity)?;
+            dither.source = Some(args.preprocess.run_f32(image.to_rgba32f()));
+            Ok(image.into_rgba8())
+        }
+        _ => load_image(r#in, args.in_format(), verbosity),
+    })?;
+    let image = args.preprocess.run(image);
+    let given = cmd.given_palette();
+    let progress = match &main_cmd {
+        Cmd::Dither(dither) if verbosity.progress() => {
+            Some(Arc::new(RowProgress::new(dither.rows(&image))))
+        }
+        _ => None,
+    };
+    let run_control = match &progress {
+        Some(progress) => {
+            let progress = progress.clone();
+            control.clone().with_progress(move || progress.row())
+        }
+        None => control.clone(),
+    };
+    let result = verbosity.timed("Processing", || main_cmd.run(image.clone(), &run_control));
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    let Some(result) = result else {
+        return Ok(());
+    };
+    if args.print_hash {
+        print_hash(&[&result]);
+    }
+
+    if let Some(path) = &args.heatmap {
+        if verbosity.progress() {
+            eprintln!("Writing heatmap to {}", path.display());
+        }
+        compare::heatmap(&image, &result)
+            .save(path)
+            .unwrap_or_else(|e| {
+                exit_with_error(format!("failed to save heatmap {}: {e}", path.display()))
+            });
+    }
+
+    if let Some(path) = &args.temporal {
+        let target = dither::temporal_target(&image, &result);
+        if let Some(second) = cmd.clone().run(target, &control) {
+            save_image(Some(path), second, &args.output, &given, verbosity);
+        }
+    }
+
+    if let Some(dir) = &args.output.bitplanes {
+        save_bitplanes(dir, &result, &args.output, &given, verbosity);
+    }
+
+    verbosity.timed("Writing", || {
+        save_image(out, result, &args.output, &given, verbosity)
+    });
+    Ok(())
+}
+
 fn main() {
-    let args = Args::parse();
-    let image = load_image(&args.r#in);
-    let image = args.cmd.run(image);
-    save_image(&args.out, image);
+    let mut args = Args::parse();
+    if args.list_palettes {
+        list_palettes();
+        return;
+    }
+    let Some(mut cmd) = args.cmd.take() else {
+        Args::command()
+            .error(ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit()
+    };
+    cmd.prepare(args.verbosity());
+    if args.shared_palette {
+        share_palette(&args, &mut cmd);
+    }
+    if args.depth == Depth::Sixteen && cmd.precise_dither_mut().is_none() {
+        eprintln!("Warning: --depth 16 only affects the dither and contact commands");
+    }
+    if matches!(cmd, Cmd::Info(_)) && args.print_hash {
+        eprintln!("Warning: --print-hash is ignored by the info command");
+    }
+    if matches!(cmd, Cmd::Contact(_)) && (args.heatmap.is_some() || args.temporal.is_some()) {
+        exit_with_error("--heatmap and --temporal can't be used with the contact command");
+    }
+
+    if let [] | [_] = &args.r#in[..] {
+        let r#in = args.r#in.first().map(PathBuf::as_path);
+        let result = process(&args, &cmd, r#in, args.out.as_deref());
+        result.unwrap_or_else(|e| exit_with_error(e));
+        return;
+    }
+
+    let Some(out) = &args.out else {
+        exit_with_error("multiple inputs require --out");
+    };
+    if args.heatmap.is_some() || args.temporal.is_some() || args.output.bitplanes.is_some() {
+        exit_with_error("--heatmap, --temporal and --bitplanes require a single input");
+    }
+
+    let total = args.r#in.len();
+    let mut failed = 0;
+    for (i, r#in) in args.r#in.iter().enumerate() {
+        if args.verbosity().progress() {
+            eprintln!("Processing {} ({}/{total})", r#in.display(), i + 1);
+        }
+        let out = batch_out_path(out, r#in);
+        if let Err(e) = process(&args, &cmd, Some(r#in), Some(&out)) {
+            eprintln!("Error: failed to process {}: {e}", r#in.display());
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        exit_with_error(format!("{failed} of {total} inputs failed"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    /// Parse a dither command with the required arguments filled in.
+    fn dither_cmd(args: &[&str]) -> DitherCmd {
+        let required = [
+            "dither",
+            "--algorithm",
+            "threshold",
+            "--color-space",
+            "oklab",
+            "--difference",
+            "euclid",
+        ];
+        DitherCmd::try_parse_from(required.iter().chain(args)).unwrap()
+    }
+
+    fn parse_color(s: &str) -> Option<Srgb<u8>> {
+        s.parse::<SrgbColor>().ok().map(|c| c.0)
+    }
+
+    #[test]
+    fn colors_parse_with_and_without_hash() {
+        let white = Some(Srgb::new(255, 255, 255));
+        assert_eq!(parse_color("#fff"), white);
+        assert_eq!(parse_color("fff"), white);
+        assert_eq!(parse_color("#ffffff"), white);
+        assert_eq!(parse_color("ffffff"), white);
+        assert_eq!(parse_color("#1a2"), Some(Srgb::new(0x11, 0xaa, 0x22)));
+        assert_eq!(parse_color("#12abEF"), Some(Srgb::new(0x12, 0xab, 0xef)));
+    }
+
+    #[test]
+    fn colors_with_invalid_lengths_are_rejected() {
+        for s in [
+            "",
+            "#",
+            "f",
+            "#ff",
+            "ffff",
+            "#fffff",
+            "fffffff",
+            "#ffffffff",
+        ] {
+            assert_eq!(parse_color(s), None, "{s:?}");
+        }
+        assert_eq!(parse_color("#ggg"), None);
+    }
+
+    fn solid(r: u8, g: u8, b: u8) -> RgbaImage {
+        RgbaImage::from_pixel(4, 4, Rgba([r, g, b, 255]))
+    }
+
+    #[test]
+    fn shared_palette_covers_every_image() {
+        let dither = dither_cmd(&["--auto-palette", "2"]);
+        let (red, blue) = (solid(255, 0, 0), solid(0, 0, 255));
+        let mut colors = dither.shared_palette(&[&red, &blue], PaletteSize::Colors(2));
+        colors.sort_by_key(|c| c.into_components());
+        assert_eq!(colors, [Srgb::new(0, 0, 255), Srgb::new(255, 0, 0)]);
+    }
+
+    #[test]
+    fn four_color_images_give_four_color_palettes() {
+        let colors = [[0, 0, 0], [255, 0, 0], [0, 255, 0], [10, 20, 30]];
+        let image = RgbaImage::from_fn(8, 8, |x, y| {
+            let [r, g, b] = colors[((x / 2 + y) % 4) as usize];
+            Rgba([r, g, b, 255])
+        });
+        let palette = unique_colors(&image).unwrap();
+        let expected = colors.map(|[r, g, b]| Srgb::new(r, g, b));
+        assert_eq!(palette, expected);
+    }
+
+    #[test]
+    fn dither_with_the_oklab_difference() {
+        let image = RgbaImage::from_fn(16, 8, |x, y| Rgba([x as u8 * 16, y as u8 * 32, 99, 255]));
+        for space in ["srgb", "cielab", "oklab", "okhsl"] {
+            let args = [
+                "dither",
+                "--algorithm",
+                "floyd-steinberg",
+                "--color-space",
+                space,
+                "--difference",
+                "oklab",
+                "--palette",
+                "#000",
+                "--palette",
+                "#f80",
+                "--palette",
+                "#fff",
+            ];
+            let mut cmd = DitherCmd::try_parse_from(args).unwrap();
+            cmd.prepare(Verbosity::Quiet);
+            let result = cmd.run(image.clone(), &Control::new()).unwrap();
+
+            let palette = [[0, 0, 0, 255], [255, 136, 0, 255], [255, 255, 255, 255]];
+            assert!(result.pixels().all(|p| palette.contains(&p.0)), "{space}");
+            assert!(
+                palette.iter().all(|c| result.pixels().any(|p| p.0 == *c)),
+                "{space}"
+            );
+        }
+    }
+
+    fn preprocess(args: &[&str]) -> Preprocess {
+        let args = ["mark-bin"].iter().chain(args);
+        Args::try_parse_from(args).unwrap().preprocess
+    }
+
+    #[test]
+    fn preview_caps_the_largest_side() {
+        let preview = preprocess(&["--preview", "500"]);
+        let Size { width, height } = preview.size((2000, 1200)).unwrap();
+        assert_eq!((width, height), (500, 300));
+        let Size { width, height } = preview.size((800, 2000)).unwrap();
+        assert_eq!((width, height), (200, 500));
+        assert!(preview.size((500, 400)).is_none());
+
+        let scaled = preprocess(&["--preview", "500", "--scale", "2"]);
+        let Size { width, height } = scaled.size((400, 100)).unwrap();
+        assert!(width <= 500 && height <= 500, "{width}x{height}");
+    }
+
+    #[test]
+    fn json_reports_stay_valid() {
+        let report = StatsReport {
+            difference: "euclid".to_string(),
+            mean: f32::NAN,
+            max: f32::INFINITY,
+        };
+        assert_eq!(
+            to_json(&report),
+            r#"{"difference":"euclid","mean":null,"max":null}"#
+        );
+        let color = SrgbColor(Srgb::new(0x12, 0xab, 0xef));
+        assert_eq!(to_json(&color), r#""12abef""#);
+    }
+
+    #[test]
+    fn tiles_blend_into_their_neighbours() {
+        // Pixels near the center of a tile mostly come from that tile.
+        for choice in [0.0, 0.5, 0.9] {
+            assert_eq!(pick_tile(4, 8, 3, choice), 0);
+            assert_eq!(pick_tile(12, 8, 3, choice), 1);
+        }
+        // Halfway between two centers, both tiles are equally likely.
+        assert_eq!(pick_tile(8, 8, 3, 0.2), 0);
+        assert_eq!(pick_tile(8, 8, 3, 0.8), 1);
+        // Tiles that don't exist are never picked.
+        assert_eq!(pick_tile(23, 8, 3, 0.99), 2);
+
+        // The single colors of two tiles mix near their boundary.
+        let (red, blue) = (Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255]));
+        let image = RgbaImage::from_fn(32, 8, |x, _| if x < 16 { red } else { blue });
+        let cmd = dither_cmd(&["--tile-palette", "16x8", "--tile-colors", "1"]);
+        let result = cmd.run(image, &Control::new()).unwrap();
+        let (left, right) = (*result.get_pixel(0, 0), *result.get_pixel(31, 0));
+        assert_ne!(left, right);
+        assert!((0..8).all(|y| *result.get_pixel(0, y) == left));
+        assert!((0..8).all(|y| *result.get_pixel(31, y) == right));
+        let seam = (0..8).flat_map(|y| [*result.get_pixel(15, y), *result.get_pixel(16, y)]);
+        let seam = seam.collect::<Vec<_>>();
+        assert!(seam.contains(&left) && seam.contains(&right));
+    }
+
+    #[test]
+    fn two_color_images_round_trip_through_1bit_pngs() {
+        let output = Args::try_parse_from(["mark-bin"]).unwrap().output;
+        let image = RgbaImage::from_fn(9, 3, |x, y| {
+            if (x + y) % 3 == 0 {
+                Rgba([200, 30, 10, 255])
+            } else {
+                Rgba([10, 20, 90, 255])
+            }
+        });
+
+        let mut png = vec![];
+        write_output_png(&mut png, &image, &output, &[]);
+
+        let reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+        assert_eq!(reader.info().bit_depth, png::BitDepth::One);
+        let decoded = image::load_from_memory(&png).unwrap().into_rgba8();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn no_dedup_is_an_alias() {
+        assert!(!dither_cmd(&["-p", "#000"]).no_palette_dedup);
+        assert!(dither_cmd(&["-p", "#000", "--no-dedup"]).no_palette_dedup);
+        assert!(dither_cmd(&["-p", "#000", "--no-palette-dedup"]).no_palette_dedup);
+    }
 }
@@ -0,0 +1,55 @@
+//! Compare nearest color lookups in large palettes.
+//!
+//! Run with `cargo bench -p mark --features simd` to measure the simd lookup
+//! against the scalar one, and without the feature for the baseline.
+
+// Benchmarks only use some of the crate's dependencies.
+#![allow(unused_crate_dependencies)]
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use mark::dither::{DiffEuclid, Difference, Palette};
+use palette::Lch;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+fn random_lch(rng: &mut SmallRng) -> Lch {
+    Lch::new(
+        rng.gen_range(0.0..100.0),
+        rng.gen_range(0.0..130.0),
+        rng.gen_range(0.0..360.0),
+    )
+}
+
+fn nearest(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(0);
+    // The hue channel keeps the palette from using a k-d tree, so every
+    // lookup compares against all 256 colors.
+    let palette = Palette::new((0..256).map(|_| random_lch(&mut rng)).collect());
+    let colors = (0..1024).map(|_| random_lch(&mut rng)).collect::<Vec<_>>();
+    let prepared = palette.prepare(&DiffEuclid);
+
+    let mut group = c.benchmark_group("nearest of 256 colors");
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            for color in &colors {
+                let nearest = (palette.colors().iter())
+                    .map(|c| DiffEuclid.diff(*c, *color))
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b));
+                black_box(nearest);
+            }
+        })
+    });
+    group.bench_function("palette", |b| {
+        b.iter(|| {
+            for color in &colors {
+                black_box(prepared.nearest(*color));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, nearest);
+criterion_main!(benches);
@@ -0,0 +1,119 @@
+//! Global adjustments applied to an image before processing it.
+
+use image::RgbaImage;
+use palette::{LinSrgb, Srgb};
+
+use crate::util;
+
+/// Adjust the brightness and contrast of an image in linear light.
+///
+/// Each channel becomes `(c - 0.5) * contrast + 0.5 + brightness`, clamped to
+/// the range `0..=1`. A brightness of 0 and contrast of 1 leave the image
+/// unchanged.
+pub fn brightness_contrast(image: &mut RgbaImage, brightness: f32, contrast: f32) {
+    let adjust = |c: f32| ((c - 0.5) * contrast + 0.5 + brightness).clamp(0.0, 1.0);
+    for pixel in image.pixels_mut() {
+        let color: LinSrgb = util::pixel_to_srgb(*pixel).into_linear();
+        let color = LinSrgb::new(adjust(color.red), adjust(color.green), adjust(color.blue));
+        util::update_pixel_with_srgb(pixel, Srgb::from_linear(color));
+    }
+}
+
+/// Composite an image over an opaque background color in linear light, making
+/// it fully opaque.
+///
+/// # Example
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use mark::adjust;
+/// use palette::Srgb;
+///
+/// let mut image = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 128]));
+/// adjust::composite_over(&mut image, Srgb::new(1.0, 1.0, 1.0));
+///
+/// // About half of the light of the red and the white, a light pink
+/// assert_eq!(image.get_pixel(0, 0), &Rgba([255, 187, 187, 255]));
+/// ```
+pub fn composite_over(image: &mut RgbaImage, background: Srgb) {
+    let background = background.into_linear();
+    for pixel in image.pixels_mut() {
+        let alpha = pixel.0[3] as f32 / 255.0;
+        let color: LinSrgb = util::pixel_to_srgb(*pixel).into_linear();
+        let color = color * alpha + background * (1.0 - alpha);
+        util::update_pixel_with_srgb(pixel, Srgb::from_linear(color));
+        pixel.0[3] = 255;
+    }
+}
+
+/// Apply a gamma curve to the gamma-encoded srgb channels of an image.
+///
+/// Each channel becomes `c.powf(1.0 / gamma)`, so a gamma above 1 brightens
+/// and a gamma below 1 darkens the midtones while black and white stay put.
+///
+/// # Panics
+///
+/// Panics if the gamma is not positive.
+pub fn gamma(image: &mut RgbaImage, gamma: f32) {
+    assert!(gamma > 0.0, "gamma must be positive");
+    let exponent = 1.0 / gamma;
+    for pixel in image.pixels_mut() {
+        let color = util::pixel_to_srgb(*pixel);
+        let color = Srgb::new(
+            color.red.powf(exponent),
+            color.green.powf(exponent),
+            color.blue.powf(exponent),
+        );
+        util::update_pixel_with_srgb(pixel, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn flat_gray(v: u8) -> RgbaImage {
+        RgbaImage::from_pixel(4, 4, Rgba([v, v, v, 255]))
+    }
+
+    /// The linear value of a flat gray image.
+    #[track_caller]
+    fn linear_gray(image: &RgbaImage) -> f32 {
+        let first = *image.get_pixel(0, 0);
+        assert!(image.pixels().all(|p| *p == first), "image is not flat");
+        let color: LinSrgb = util::pixel_to_srgb(first).into_linear();
+        assert_eq!((color.red, color.green), (color.blue, color.blue));
+        color.red
+    }
+
+    #[track_caller]
+    fn assert_adjusts(v: u8, brightness: f32, contrast: f32, expected: impl Fn(f32) -> f32) {
+        let mut image = flat_gray(v);
+        let before = linear_gray(&image);
+        brightness_contrast(&mut image, brightness, contrast);
+        let after = linear_gray(&image);
+        // Rounding to 8 bits shifts linear values by less than 0.01.
+        let expected = expected(before);
+        assert!(
+            (after - expected).abs() < 0.01,
+            "expected {expected}, got {after}"
+        );
+    }
+
+    #[test]
+    fn brightness_shifts_flat_gray_in_linear_light() {
+        assert_adjusts(188, 0.1, 1.0, |c| c + 0.1);
+        assert_adjusts(188, -0.2, 1.0, |c| c - 0.2);
+        assert_adjusts(100, 0.3, 1.0, |c| c + 0.3);
+        assert_adjusts(250, 0.5, 1.0, |_| 1.0);
+    }
+
+    #[test]
+    fn contrast_scales_around_linear_mid_gray() {
+        assert_adjusts(188, 0.0, 3.0, |_| 0.5);
+        assert_adjusts(150, 0.0, 2.0, |c| (c - 0.5) * 2.0 + 0.5);
+        assert_adjusts(50, 0.0, 4.0, |_| 0.0);
+    }
+}
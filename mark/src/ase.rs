@@ -0,0 +1,211 @@
+//! Reading Adobe Swatch Exchange (.ase) palettes.
+//!
+//! An ase file starts with the signature `ASEF`, a version and the number of
+//! blocks. Each block has a type, a length and some data. All numbers are big
+//! endian.
+
+use std::{error::Error, fmt};
+
+use palette::Srgb;
+
+/// The magic bytes every ase file starts with.
+pub const SIGNATURE: &[u8; 4] = b"ASEF";
+
+const BLOCK_COLOR: u16 = 0x0001;
+const BLOCK_GROUP_START: u16 = 0xc001;
+const BLOCK_GROUP_END: u16 = 0xc002;
+
+#[derive(Debug)]
+pub enum AseError {
+    MissingSignature,
+    UnexpectedEnd,
+    UnknownBlockType(u16),
+    /// Only rgb and gray colors can be converted to srgb.
+    UnsupportedColorModel(String),
+}
+
+impl fmt::Display for AseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingSignature => write!(f, "not an ase file"),
+            Self::UnexpectedEnd => write!(f, "ase file ended unexpectedly"),
+            Self::UnknownBlockType(t) => write!(f, "unknown ase block type {t:#06x}"),
+            Self::UnsupportedColorModel(m) => {
+                write!(
+                    f,
+                    "unsupported ase color model {m:?}, only RGB and Gray are supported"
+                )
+            }
+        }
+    }
+}
+
+impl Error for AseError {}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], AseError> {
+        if self.bytes.len() < n {
+            return Err(AseError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], AseError> {
+        Ok(self.take(N)?.try_into().expect("slice has length N"))
+    }
+
+    fn u16(&mut self) -> Result<u16, AseError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, AseError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, AseError> {
+        Ok(f32::from_be_bytes(self.array()?))
+    }
+}
+
+fn parse_color(data: &[u8]) -> Result<Srgb<u8>, AseError> {
+    let mut reader = Reader { bytes: data };
+
+    // The name is a null-terminated utf-16 string, its length is given in
+    // code units.
+    let name_len = reader.u16()?;
+    reader.take(name_len as usize * 2)?;
+
+    let model = reader.array::<4>()?;
+    let color = match &model {
+        b"RGB " => Srgb::new(reader.f32()?, reader.f32()?, reader.f32()?),
+        b"Gray" => {
+            let value = reader.f32()?;
+            Srgb::new(value, value, value)
+        }
+        _ => {
+            let model = String::from_utf8_lossy(&model).trim().to_string();
+            return Err(AseError::UnsupportedColorModel(model));
+        }
+    };
+    Ok(color.into_format())
+}
+
+/// Parse the colors of an ase file in order, ignoring groups.
+pub fn parse(bytes: &[u8]) -> Result<Vec<Srgb<u8>>, AseError> {
+    let mut reader = Reader { bytes };
+    if reader.take(4).ok() != Some(SIGNATURE.as_slice()) {
+        return Err(AseError::MissingSignature);
+    }
+    let _version = reader.u32()?;
+    let blocks = reader.u32()?;
+
+    let mut colors = vec![];
+    for _ in 0..blocks {
+        let block_type = reader.u16()?;
+        let len = reader.u32()?;
+        let data = reader.take(len as usize)?;
+        match block_type {
+            BLOCK_COLOR => colors.push(parse_color(data)?),
+            BLOCK_GROUP_START | BLOCK_GROUP_END => {}
+            _ => return Err(AseError::UnknownBlockType(block_type)),
+        }
+    }
+    Ok(colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ase file consisting of the given blocks.
+    fn file(blocks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(0x0001_0000_u32.to_be_bytes());
+        bytes.extend((blocks.len() as u32).to_be_bytes());
+        for block in blocks {
+            bytes.extend(block);
+        }
+        bytes
+    }
+
+    fn block(block_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = block_type.to_be_bytes().to_vec();
+        bytes.extend((data.len() as u32).to_be_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    /// A color block named "c" with the given model and values.
+    fn color(model: &[u8; 4], values: &[f32]) -> Vec<u8> {
+        let mut data = 2_u16.to_be_bytes().to_vec();
+        data.extend([0, b'c', 0, 0]);
+        data.extend(model);
+        for value in values {
+            data.extend(value.to_be_bytes());
+        }
+        // The color type, e.g. global or spot.
+        data.extend(0_u16.to_be_bytes());
+        block(BLOCK_COLOR, &data)
+    }
+
+    #[test]
+    fn rgb_and_gray_colors_are_parsed() {
+        let bytes = file(&[color(b"RGB ", &[1.0, 0.5, 0.0]), color(b"Gray", &[0.2])]);
+        let colors = parse(&bytes).unwrap();
+        assert_eq!(colors, [Srgb::new(255, 128, 0), Srgb::new(51, 51, 51)]);
+    }
+
+    #[test]
+    fn groups_are_skipped() {
+        let mut group_start = 3_u16.to_be_bytes().to_vec();
+        group_start.extend([0, b'g', 0, b'1', 0, 0]);
+        let bytes = file(&[
+            block(BLOCK_GROUP_START, &group_start),
+            color(b"RGB ", &[0.0, 0.0, 1.0]),
+            block(BLOCK_GROUP_END, &[]),
+            color(b"Gray", &[1.0]),
+        ]);
+        let colors = parse(&bytes).unwrap();
+        assert_eq!(colors, [Srgb::new(0, 0, 255), Srgb::new(255, 255, 255)]);
+    }
+
+    #[test]
+    fn cmyk_and_lab_colors_are_unsupported() {
+        for (model, values) in [
+            (b"CMYK", &[0.0, 0.5, 1.0, 0.0][..]),
+            (b"LAB ", &[50.0, 0.0, 0.0]),
+        ] {
+            let result = parse(&file(&[color(model, values)]));
+            assert!(
+                matches!(result, Err(AseError::UnsupportedColorModel(_))),
+                "{result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_blocks_are_rejected() {
+        let mut bytes = file(&[color(b"RGB ", &[1.0, 1.0, 1.0])]);
+        bytes.truncate(bytes.len() - 3);
+        assert!(matches!(parse(&bytes), Err(AseError::UnexpectedEnd)));
+
+        // A block whose data ends before its color does.
+        let truncated = block(BLOCK_COLOR, &color(b"RGB ", &[1.0])[6..]);
+        let result = parse(&file(&[truncated]));
+        assert!(matches!(result, Err(AseError::UnexpectedEnd)));
+    }
+
+    #[test]
+    fn files_without_signature_are_rejected() {
+        let mut bytes = file(&[color(b"Gray", &[0.5])]);
+        bytes[..4].copy_from_slice(b"GPL ");
+        assert!(matches!(parse(&bytes), Err(AseError::MissingSignature)));
+        assert!(matches!(parse(b"AS"), Err(AseError::MissingSignature)));
+    }
+}
@@ -3,7 +3,7 @@ use palette::{Hsl, Hsv, IntoColor, Lab, LinSrgb, Oklab, Srgb};
 
 use crate::util;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Method {
     SrgbAverage,
     LinSrgbAverage,
@@ -11,6 +11,28 @@ pub enum Method {
     Hsv,
     Cielab,
     Oklab,
+    /// Perceived brightness according to the HSP color model.
+    Hsp,
+    /// Rec. 601 luma of the gamma-encoded channels, as used by many legacy
+    /// tools.
+    Rec601Luma,
+    /// Rec. 709 luminance of the linear channels.
+    Rec709Luma,
+    /// Custom weights for the gamma-encoded channels.
+    ///
+    /// The weights don't need to sum to 1, the result is clamped.
+    Weighted {
+        r: f32,
+        g: f32,
+        b: f32,
+    },
+    /// The midpoint between the largest and smallest channel.
+    Lightness,
+    /// The largest channel.
+    Value,
+    /// The Rec. 709 weights applied to the gamma-encoded channels, like the
+    /// luminosity desaturation of image editors.
+    Luminosity,
 }
 
 impl Method {
@@ -47,6 +69,41 @@ impl Method {
                 pixel.b = 0.0;
                 pixel.into_color()
             }
+            Self::Hsp => {
+                // Squaring the gamma-encoded channels roughly linearizes them.
+                let value = (0.299 * pixel.red.powi(2)
+                    + 0.587 * pixel.green.powi(2)
+                    + 0.114 * pixel.blue.powi(2))
+                .sqrt();
+                Srgb::new(value, value, value)
+            }
+            Self::Rec601Luma => {
+                let value = 0.299 * pixel.red + 0.587 * pixel.green + 0.114 * pixel.blue;
+                Srgb::new(value, value, value)
+            }
+            Self::Rec709Luma => {
+                let pixel: LinSrgb = pixel.into_color();
+                let value = 0.2126 * pixel.red + 0.7152 * pixel.green + 0.0722 * pixel.blue;
+                LinSrgb::new(value, value, value).into_color()
+            }
+            Self::Weighted { r, g, b } => {
+                let value = (r * pixel.red + g * pixel.green + b * pixel.blue).clamp(0.0, 1.0);
+                Srgb::new(value, value, value)
+            }
+            Self::Lightness => {
+                let max = pixel.red.max(pixel.green).max(pixel.blue);
+                let min = pixel.red.min(pixel.green).min(pixel.blue);
+                let value = (max + min) / 2.0;
+                Srgb::new(value, value, value)
+            }
+            Self::Value => {
+                let value = pixel.red.max(pixel.green).max(pixel.blue);
+                Srgb::new(value, value, value)
+            }
+            Self::Luminosity => {
+                let value = 0.2126 * pixel.red + 0.7152 * pixel.green + 0.0722 * pixel.blue;
+                Srgb::new(value, value, value)
+            }
         }
     }
 }
@@ -58,3 +115,142 @@ pub fn bw(image: &mut RgbaImage, method: Method) {
         util::update_pixel_with_srgb(pixel, srgb);
     }
 }
+
+/// Like [`bw`], but leaves fully transparent pixels untouched.
+pub fn bw_skip_transparent(image: &mut RgbaImage, method: Method) {
+    for pixel in image.pixels_mut().filter(|p| p.0[3] > 0) {
+        let srgb = util::pixel_to_srgb(*pixel);
+        let srgb = method.to_bw(srgb);
+        util::update_pixel_with_srgb(pixel, srgb);
+    }
+}
+
+/// Tone a black and white image with a gradient between two colors.
+///
+/// Each pixel's luminance `v` is mapped to `lerp(shadow, highlight, v)` in
+/// linear light, so black becomes the shadow color and white the highlight
+/// color. A dark brown shadow and cream highlight give a sepia look.
+pub fn tone(image: &mut RgbaImage, shadow: Srgb, highlight: Srgb) {
+    tone_pixels(image, shadow, highlight, false);
+}
+
+/// Like [`tone`], but leaves fully transparent pixels untouched.
+pub fn tone_skip_transparent(image: &mut RgbaImage, shadow: Srgb, highlight: Srgb) {
+    tone_pixels(image, shadow, highlight, true);
+}
+
+fn tone_pixels(image: &mut RgbaImage, shadow: Srgb, highlight: Srgb, skip_transparent: bool) {
+    let shadow = shadow.into_linear();
+    let highlight = highlight.into_linear();
+    for pixel in image.pixels_mut() {
+        if skip_transparent && pixel.0[3] == 0 {
+            continue;
+        }
+        let color: LinSrgb = util::pixel_to_srgb(*pixel).into_linear();
+        let v = 0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue;
+        let toned = shadow + (highlight - shadow) * v;
+        util::update_pixel_with_srgb(pixel, Srgb::from_linear(toned));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    /// The gray value a method gives a color.
+    fn gray(method: Method, r: f32, g: f32, b: f32) -> f32 {
+        let bw = method.to_bw(Srgb::new(r, g, b));
+        assert_eq!((bw.red, bw.green), (bw.blue, bw.blue));
+        bw.red
+    }
+
+    #[test]
+    fn hsp_weights_channels_unlike_the_average() {
+        let average = |r, g, b| gray(Method::SrgbAverage, r, g, b);
+        let hsp = |r, g, b| gray(Method::Hsp, r, g, b);
+
+        // The average rates all primaries the same, HSP rates green as much
+        // brighter than blue.
+        assert_eq!(average(0.0, 1.0, 0.0), average(0.0, 0.0, 1.0));
+        assert!(hsp(0.0, 1.0, 0.0) > 2.0 * hsp(0.0, 0.0, 1.0));
+        assert!((hsp(0.0, 1.0, 0.0) - 0.587_f32.sqrt()).abs() < 1e-6);
+
+        // Both agree on grays.
+        for v in [0.0, 0.25, 1.0] {
+            assert!((hsp(v, v, v) - average(v, v, v)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn lightness_value_and_luminosity_of_known_colors() {
+        // Color, then its lightness, value and luminosity.
+        let table = [
+            ([0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+            ([1.0, 1.0, 1.0], [1.0, 1.0, 1.0]),
+            ([1.0, 0.0, 0.0], [0.5, 1.0, 0.2126]),
+            ([0.0, 1.0, 0.0], [0.5, 1.0, 0.7152]),
+            ([0.0, 0.0, 1.0], [0.5, 1.0, 0.0722]),
+            ([1.0, 0.5, 0.0], [0.5, 1.0, 0.5702]),
+            ([0.2, 0.4, 0.6], [0.4, 0.6, 0.37192]),
+        ];
+        let methods = [
+            ("lightness", Method::Lightness),
+            ("value", Method::Value),
+            ("luminosity", Method::Luminosity),
+        ];
+        for ([r, g, b], expected) in table {
+            for ((name, method), expected) in methods.into_iter().zip(expected) {
+                let actual = gray(method, r, g, b);
+                assert!(
+                    (actual - expected).abs() < 1e-6,
+                    "{name} of {r},{g},{b} is {actual}, expected {expected}"
+                );
+            }
+        }
+    }
+
+    const SHADOW: Srgb = Srgb::new(0.2, 0.12, 0.05);
+    const HIGHLIGHT: Srgb = Srgb::new(0.95, 0.88, 0.72);
+
+    /// Tone a single opaque gray pixel.
+    fn toned(v: u8) -> [u8; 3] {
+        let mut image = RgbaImage::from_pixel(1, 1, Rgba([v, v, v, 255]));
+        tone(&mut image, SHADOW, HIGHLIGHT);
+        let [r, g, b, _] = image.get_pixel(0, 0).0;
+        [r, g, b]
+    }
+
+    fn to_u8(color: Srgb) -> [u8; 3] {
+        color.into_format::<u8>().into()
+    }
+
+    #[test]
+    fn tone_lerps_between_shadow_and_highlight() {
+        assert_eq!(toned(0), to_u8(SHADOW));
+        assert_eq!(toned(255), to_u8(HIGHLIGHT));
+
+        // Srgb 188 has about half of the light of white.
+        let mid = toned(188);
+        let (shadow, highlight) = (SHADOW.into_linear(), HIGHLIGHT.into_linear());
+        let expected: LinSrgb = (shadow + highlight) * 0.5;
+        let expected = to_u8(Srgb::from_linear(expected));
+        for ((m, e), (s, h)) in mid
+            .into_iter()
+            .zip(expected)
+            .zip(to_u8(SHADOW).into_iter().zip(to_u8(HIGHLIGHT)))
+        {
+            assert!(s < m && m < h, "{m} is not between {s} and {h}");
+            assert!(m.abs_diff(e) <= 1, "expected {e}, got {m}");
+        }
+    }
+
+    #[test]
+    fn tone_skip_transparent_leaves_transparent_pixels() {
+        let mut image = RgbaImage::from_fn(2, 1, |x, _| Rgba([128, 128, 128, 255 * x as u8]));
+        tone_skip_transparent(&mut image, SHADOW, HIGHLIGHT);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([128, 128, 128, 0]));
+        assert_ne!(image.get_pixel(1, 0), &Rgba([128, 128, 128, 255]));
+    }
+}
@@ -0,0 +1,76 @@
+//! Comparing images with each other.
+
+use image::{GrayImage, Luma, RgbaImage};
+use palette::{color_difference::Ciede2000, IntoColor, Lab, Srgb};
+
+use crate::{dither::Difference, util};
+
+/// The CIEDE2000 difference shown as white in a heatmap.
+pub const HEATMAP_MAX_DIFFERENCE: f32 = 50.0;
+
+/// Visualize the per-pixel CIEDE2000 difference between two images of the same
+/// size.
+///
+/// Identical pixels are black. Pixels whose difference is at least
+/// [`HEATMAP_MAX_DIFFERENCE`] are white.
+pub fn heatmap(a: &RgbaImage, b: &RgbaImage) -> GrayImage {
+    assert_eq!(a.dimensions(), b.dimensions(), "images differ in size");
+    GrayImage::from_fn(a.width(), a.height(), |x, y| {
+        let a: Lab = util::pixel_to_srgb(*a.get_pixel(x, y)).into_color();
+        let b: Lab = util::pixel_to_srgb(*b.get_pixel(x, y)).into_color();
+        let value = (a.difference(b) / HEATMAP_MAX_DIFFERENCE).clamp(0.0, 1.0);
+        Luma([(value * 255.0).round() as u8])
+    })
+}
+
+/// Summary of the per-pixel differences between two images.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DifferenceStats {
+    pub mean: f32,
+    pub max: f32,
+}
+
+/// Measure the per-pixel difference between two images of the same size in a
+/// color space.
+///
+/// Pixels that are transparent in `a` are ignored. The results are in the
+/// units of the difference.
+///
+/// # Example
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use mark::{compare, dither::DiffCiede2000};
+/// use palette::Lab;
+///
+/// let a = RgbaImage::from_fn(4, 4, |x, _| Rgba([255 * (x % 2) as u8, 0, 0, 255]));
+/// let stats = compare::difference_stats::<Lab, _>(&a, &a, &DiffCiede2000);
+///
+/// assert_eq!(stats.mean, 0.0);
+/// assert_eq!(stats.max, 0.0);
+/// ```
+pub fn difference_stats<C, D>(a: &RgbaImage, b: &RgbaImage, difference: &D) -> DifferenceStats
+where
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    assert_eq!(a.dimensions(), b.dimensions(), "images differ in size");
+    let mut sum = 0.0;
+    let mut max = 0.0_f32;
+    let mut count = 0;
+    for (a, b) in a.pixels().zip(b.pixels()) {
+        if a.0[3] == 0 {
+            continue;
+        }
+        let diff = difference.diff(util::pixel_to_color(*a), util::pixel_to_color(*b));
+        sum += diff as f64;
+        max = max.max(diff);
+        count += 1;
+    }
+    let mean = if count == 0 {
+        0.0
+    } else {
+        (sum / count as f64) as f32
+    };
+    DifferenceStats { mean, max }
+}
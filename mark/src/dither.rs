@@ -5,14 +5,16 @@
 //! compares two colors. Instead, a version of each algorithm should be compiled
 //! for each color space and difference combination.
 
-use std::marker::PhantomData;
+use std::{collections::HashSet, error::Error, f32::consts::TAU, fmt, sync::Arc, time::Instant};
 
-use image::RgbaImage;
+use image::{GrayImage, Pixel, Rgba, Rgba32FImage, RgbaImage};
 use palette::{
     color_difference::{Ciede2000, HyAb},
-    Clamp, IntoColor, Lab, Srgb,
+    Clamp, IntoColor, Lab, Lch, LinSrgb, Luv, Okhsl, Okhsv, Oklab, Srgb,
 };
 use rand::{rngs::SmallRng, Rng, SeedableRng};
+#[cfg(feature = "rayon")]
+use rayon::{iter::IndexedParallelIterator, iter::ParallelIterator, slice::ParallelSliceMut};
 
 use crate::util;
 
@@ -20,37 +22,275 @@ use crate::util;
 // Color difference //
 //////////////////////
 
+/// A difference between two colors.
+///
+/// Differences are values so they can carry configuration like weights. Most
+/// are unit structs though, and every algorithm is still compiled separately
+/// for each difference type.
 pub trait Difference<C> {
-    fn diff(a: C, b: C) -> f32;
+    /// Whether the difference is the euclidean distance between the channels
+    /// of two colors.
+    ///
+    /// Palettes can find the nearest color for such differences without
+    /// comparing against every palette color.
+    const EUCLIDEAN: bool = false;
+
+    /// A color converted ahead of time for comparisons.
+    type Prepared: Copy + Sync;
+
+    /// Convert a color for comparisons, for example to cielab.
+    ///
+    /// Algorithms prepare each palette color once instead of converting it
+    /// again for every pixel.
+    fn prepare(&self, color: C) -> Self::Prepared;
+
+    fn diff_prepared(&self, a: Self::Prepared, b: Self::Prepared) -> f32;
+
+    fn diff(&self, a: C, b: C) -> f32 {
+        self.diff_prepared(self.prepare(a), self.prepare(b))
+    }
+
+    /// The index of the prepared color nearest to another one, or of the
+    /// farthest one if `farthest` is set. Ties go to the first nearest and the
+    /// last farthest color.
+    ///
+    /// Differences may override this to compare several colors at once.
+    fn nearest_prepared(
+        &self,
+        colors: &[Self::Prepared],
+        to: Self::Prepared,
+        farthest: bool,
+    ) -> Option<usize> {
+        let differences = colors.iter().map(|c| self.diff_prepared(*c, to));
+        let cmp = |(_, a): &(usize, f32), (_, b): &(usize, f32)| a.total_cmp(b);
+        if farthest {
+            differences.enumerate().max_by(cmp)
+        } else {
+            differences.enumerate().min_by(cmp)
+        }
+        .map(|(i, _)| i)
+    }
+}
+
+/// Colors whose channels may include a hue angle.
+///
+/// Hue angles wrap around, so a hue of 359 degrees is near a hue of 1 degree.
+/// Channel-wise differences and error diffusion take this into account.
+pub trait HueChannel {
+    /// The index of the channel holding a hue angle in degrees, if any.
+    const HUE: Option<usize> = None;
+}
+
+impl HueChannel for Srgb {}
+impl HueChannel for LinSrgb {}
+impl HueChannel for Lab {}
+impl HueChannel for Luv {}
+impl HueChannel for Oklab {}
+
+impl HueChannel for Lch {
+    const HUE: Option<usize> = Some(2);
+}
+
+impl HueChannel for Okhsl {
+    const HUE: Option<usize> = Some(0);
+}
+
+impl HueChannel for Okhsv {
+    const HUE: Option<usize> = Some(0);
 }
 
-pub struct DiffClamp<D> {
-    _phantom: PhantomData<D>,
+/// The difference between each channel of two colors, with the hue difference
+/// wrapped to the range `-180..180`.
+fn channel_deltas<C: AsRef<[f32; 3]> + HueChannel>(a: C, b: C) -> [f32; 3] {
+    let [a1, a2, a3] = a.as_ref();
+    let [b1, b2, b3] = b.as_ref();
+    let mut deltas = [a1 - b1, a2 - b2, a3 - b3];
+    if let Some(i) = C::HUE {
+        deltas[i] = (deltas[i] + 180.0).rem_euclid(360.0) - 180.0;
+    }
+    deltas
 }
 
+pub struct DiffClamp<D>(pub D);
+
 impl<C: Clamp, D: Difference<C>> Difference<C> for DiffClamp<D> {
-    fn diff(a: C, b: C) -> f32 {
-        D::diff(a.clamp(), b.clamp())
+    type Prepared = D::Prepared;
+
+    fn prepare(&self, color: C) -> Self::Prepared {
+        self.0.prepare(color.clamp())
+    }
+
+    fn diff_prepared(&self, a: Self::Prepared, b: Self::Prepared) -> f32 {
+        self.0.diff_prepared(a, b)
     }
 }
 
 pub struct DiffEuclid;
 
-impl<C: AsRef<[f32; 3]>> Difference<C> for DiffEuclid {
-    fn diff(a: C, b: C) -> f32 {
-        let [a1, a2, a3] = a.as_ref();
-        let [b1, b2, b3] = b.as_ref();
-        let squared = (a1 - b1).powi(2) + (a2 - b2).powi(2) + (a3 - b3).powi(2);
-        squared.sqrt()
+impl<C: AsRef<[f32; 3]> + Copy + HueChannel + Sync> Difference<C> for DiffEuclid {
+    // The k-d tree can't handle a wrapping hue channel.
+    const EUCLIDEAN: bool = C::HUE.is_none();
+
+    type Prepared = C;
+
+    fn prepare(&self, color: C) -> C {
+        color
+    }
+
+    fn diff_prepared(&self, a: C, b: C) -> f32 {
+        let [d1, d2, d3] = channel_deltas(a, b);
+        (d1.powi(2) + d2.powi(2) + d3.powi(2)).sqrt()
+    }
+
+    #[cfg(feature = "simd")]
+    fn nearest_prepared(&self, colors: &[C], to: C, farthest: bool) -> Option<usize> {
+        simd::nearest_euclid(colors, to, farthest)
+    }
+}
+
+/// Euclidean nearest color lookups comparing four palette colors at once.
+#[cfg(feature = "simd")]
+mod simd {
+    use wide::{f32x4, CmpGe, CmpLt};
+
+    use super::{channel_deltas, HueChannel};
+
+    /// See [`super::Difference::nearest_prepared`].
+    pub(super) fn nearest_euclid<C>(colors: &[C], to: C, farthest: bool) -> Option<usize>
+    where
+        C: AsRef<[f32; 3]> + Copy + HueChannel,
+    {
+        let mut target = *to.as_ref();
+        if let Some(i) = C::HUE {
+            target[i] = target[i].rem_euclid(360.0);
+        }
+        let target = target.map(f32x4::splat);
+        let is_better = |distance: f32x4, best: f32x4| {
+            if farthest {
+                distance.cmp_ge(best)
+            } else {
+                distance.cmp_lt(best)
+            }
+        };
+
+        let worst = if farthest {
+            f32::NEG_INFINITY
+        } else {
+            f32::INFINITY
+        };
+        let mut best = f32x4::splat(worst);
+        let mut best_index = f32x4::splat(-1.0);
+        let mut index = f32x4::from([0.0, 1.0, 2.0, 3.0]);
+        let chunks = colors.chunks_exact(4);
+        let rest = chunks.remainder();
+        for chunk in chunks {
+            let mut channels = [[0.0; 4]; 3];
+            for (j, color) in chunk.iter().enumerate() {
+                for (channel, value) in channels.iter_mut().zip(color.as_ref()) {
+                    channel[j] = *value;
+                }
+            }
+            let mut squared = f32x4::splat(0.0);
+            for (i, (channel, target)) in channels.into_iter().zip(target).enumerate() {
+                let mut delta = f32x4::from(channel) - target;
+                if C::HUE == Some(i) {
+                    // Wrap to -180..180 like channel_deltas. Palette hues from
+                    // color conversions lie within -180..360, so the delta to
+                    // the normalized target hue is less than one turn off.
+                    let full = f32x4::splat(360.0);
+                    delta = delta.cmp_ge(f32x4::splat(180.0)).blend(delta - full, delta);
+                    delta = delta
+                        .cmp_lt(f32x4::splat(-180.0))
+                        .blend(delta + full, delta);
+                }
+                squared += delta * delta;
+            }
+            let distance = squared.sqrt();
+            let better = is_better(distance, best);
+            best = better.blend(distance, best);
+            best_index = better.blend(index, best_index);
+            index += f32x4::splat(4.0);
+        }
+
+        // Within a lane, later colors only win when they are strictly nearer
+        // or at least as far, so the lanes only need the same tie-breaking.
+        let mut result = None::<(usize, f32)>;
+        let lanes = best_index.to_array().into_iter().zip(best.to_array());
+        for (i, distance) in lanes.filter(|(i, _)| *i >= 0.0) {
+            let i = i as usize;
+            let wins = match result {
+                None => true,
+                Some((j, best)) if farthest => distance > best || (distance == best && i > j),
+                Some((j, best)) => distance < best || (distance == best && i < j),
+            };
+            if wins {
+                result = Some((i, distance));
+            }
+        }
+
+        let offset = colors.len() - rest.len();
+        for (i, color) in (offset..).zip(rest) {
+            let [d1, d2, d3] = channel_deltas(*color, to);
+            let distance = (d1.powi(2) + d2.powi(2) + d3.powi(2)).sqrt();
+            let wins = match result {
+                None => true,
+                Some((_, best)) if farthest => distance >= best,
+                Some((_, best)) => distance < best,
+            };
+            if wins {
+                result = Some((i, distance));
+            }
+        }
+        // Only colors at a NaN or infinite distance are never better.
+        let fallback = (!colors.is_empty()).then_some(0);
+        result.map(|(i, _)| i).or(fallback)
+    }
+}
+
+/// The euclidean distance with a weight for each channel.
+///
+/// Useful to emphasize lightness over chroma or vice versa.
+pub struct DiffWeightedEuclid {
+    weights: [f32; 3],
+}
+
+impl DiffWeightedEuclid {
+    /// # Panics
+    ///
+    /// Panics if any weight is negative or not finite.
+    pub fn new(weights: [f32; 3]) -> Self {
+        assert!(
+            weights.iter().all(|w| w.is_finite() && *w >= 0.0),
+            "weights must be finite and must not be negative"
+        );
+        Self { weights }
+    }
+}
+
+impl<C: AsRef<[f32; 3]> + Copy + HueChannel + Sync> Difference<C> for DiffWeightedEuclid {
+    type Prepared = C;
+
+    fn prepare(&self, color: C) -> C {
+        color
+    }
+
+    fn diff_prepared(&self, a: C, b: C) -> f32 {
+        let [d1, d2, d3] = channel_deltas(a, b);
+        let [w1, w2, w3] = self.weights;
+        (w1 * d1.powi(2) + w2 * d2.powi(2) + w3 * d3.powi(2)).sqrt()
     }
 }
 
 pub struct DiffHyAb;
 
 impl<C: IntoColor<Lab>> Difference<C> for DiffHyAb {
-    fn diff(a: C, b: C) -> f32 {
-        let a: Lab = a.into_color();
-        let b: Lab = b.into_color();
+    type Prepared = Lab;
+
+    fn prepare(&self, color: C) -> Lab {
+        color.into_color()
+    }
+
+    fn diff_prepared(&self, a: Lab, b: Lab) -> f32 {
         a.hybrid_distance(b)
     }
 }
@@ -58,20 +298,263 @@ impl<C: IntoColor<Lab>> Difference<C> for DiffHyAb {
 pub struct DiffCiede2000;
 
 impl<C: IntoColor<Lab>> Difference<C> for DiffCiede2000 {
-    fn diff(a: C, b: C) -> f32 {
-        let a: Lab = a.into_color();
-        let b: Lab = b.into_color();
+    type Prepared = Lab;
+
+    fn prepare(&self, color: C) -> Lab {
+        color.into_color()
+    }
+
+    fn diff_prepared(&self, a: Lab, b: Lab) -> f32 {
         a.difference(b)
     }
 }
 
+/// The CIEDE2000 difference with custom parametric weighting factors.
+///
+/// With all factors set to 1, this is the same as [`DiffCiede2000`].
+#[derive(Debug, Clone, Copy)]
+pub struct DiffCiede2000Weighted {
+    /// Weight of lightness differences.
+    pub kl: f32,
+    /// Weight of chroma differences.
+    pub kc: f32,
+    /// Weight of hue differences.
+    pub kh: f32,
+}
+
+impl<C: IntoColor<Lab>> Difference<C> for DiffCiede2000Weighted {
+    type Prepared = Lab;
+
+    fn prepare(&self, color: C) -> Lab {
+        color.into_color()
+    }
+
+    fn diff_prepared(&self, a: Lab, b: Lab) -> f32 {
+        const POW_25_7: f32 = 6_103_515_625.0;
+        let chroma_factor = |c: f32| (c.powi(7) / (c.powi(7) + POW_25_7)).sqrt();
+        let hue = |b: f32, a: f32| {
+            if a == 0.0 && b == 0.0 {
+                0.0
+            } else {
+                b.atan2(a).to_degrees().rem_euclid(360.0)
+            }
+        };
+
+        let c_mean = (a.a.hypot(a.b) + b.a.hypot(b.b)) / 2.0;
+        let g = 0.5 * (1.0 - chroma_factor(c_mean));
+        let (a1, a2) = ((1.0 + g) * a.a, (1.0 + g) * b.a);
+        let (c1, c2) = (a1.hypot(a.b), a2.hypot(b.b));
+        let (h1, h2) = (hue(a.b, a1), hue(b.b, a2));
+
+        let dl = b.l - a.l;
+        let dc = c2 - c1;
+        let dh = match h2 - h1 {
+            _ if c1 * c2 == 0.0 => 0.0,
+            d if d > 180.0 => d - 360.0,
+            d if d < -180.0 => d + 360.0,
+            d => d,
+        };
+        let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+
+        let l_mean = (a.l + b.l) / 2.0;
+        let c_mean = (c1 + c2) / 2.0;
+        let h_mean = if c1 * c2 == 0.0 {
+            h1 + h2
+        } else if (h1 - h2).abs() <= 180.0 {
+            (h1 + h2) / 2.0
+        } else if h1 + h2 < 360.0 {
+            (h1 + h2 + 360.0) / 2.0
+        } else {
+            (h1 + h2 - 360.0) / 2.0
+        };
+
+        let cos = |degrees: f32| degrees.to_radians().cos();
+        let t = 1.0 - 0.17 * cos(h_mean - 30.0)
+            + 0.24 * cos(2.0 * h_mean)
+            + 0.32 * cos(3.0 * h_mean + 6.0)
+            - 0.20 * cos(4.0 * h_mean - 63.0);
+        let d_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+        let rc = 2.0 * chroma_factor(c_mean);
+        let sl = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+        let sc = 1.0 + 0.045 * c_mean;
+        let sh = 1.0 + 0.015 * c_mean * t;
+        let rt = -(2.0 * d_theta).to_radians().sin() * rc;
+
+        let l = dl / (self.kl * sl);
+        let c = dc / (self.kc * sc);
+        let h = dh / (self.kh * sh);
+        (l.powi(2) + c.powi(2) + h.powi(2) + rt * c * h).sqrt()
+    }
+}
+
+/// The euclidean distance in cielab, also known as CIE76.
+pub struct DiffCie76;
+
+impl<C: IntoColor<Lab>> Difference<C> for DiffCie76 {
+    type Prepared = Lab;
+
+    fn prepare(&self, color: C) -> Lab {
+        color.into_color()
+    }
+
+    fn diff_prepared(&self, a: Lab, b: Lab) -> f32 {
+        ((a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)).sqrt()
+    }
+}
+
+/// The euclidean distance in oklab.
+///
+/// In the oklab color space, this is the same as [`DiffEuclid`], which can use
+/// a faster nearest color search.
+pub struct DiffOklab;
+
+impl<C: IntoColor<Oklab>> Difference<C> for DiffOklab {
+    type Prepared = Oklab;
+
+    fn prepare(&self, color: C) -> Oklab {
+        color.into_color()
+    }
+
+    fn diff_prepared(&self, a: Oklab, b: Oklab) -> f32 {
+        ((a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)).sqrt()
+    }
+}
+
+/// The CIE94 difference.
+///
+/// CIE94 is not symmetric, `a` is treated as the reference color.
+#[derive(Debug, Clone, Copy)]
+pub struct DiffCie94 {
+    /// Weight of lightness differences, usually 1 or 2.
+    pub kl: f32,
+    /// Influence of the chroma on the chroma weighting function.
+    pub k1: f32,
+    /// Influence of the chroma on the hue weighting function.
+    pub k2: f32,
+}
+
+impl DiffCie94 {
+    pub const GRAPHIC_ARTS: Self = Self {
+        kl: 1.0,
+        k1: 0.045,
+        k2: 0.015,
+    };
+
+    pub const TEXTILES: Self = Self {
+        kl: 2.0,
+        k1: 0.048,
+        k2: 0.014,
+    };
+}
+
+impl<C: IntoColor<Lab>> Difference<C> for DiffCie94 {
+    type Prepared = Lab;
+
+    fn prepare(&self, color: C) -> Lab {
+        color.into_color()
+    }
+
+    fn diff_prepared(&self, a: Lab, b: Lab) -> f32 {
+        let c1 = a.a.hypot(a.b);
+        let c2 = b.a.hypot(b.b);
+        let dl = a.l - b.l;
+        let dc = c1 - c2;
+        // Rounding errors can make this slightly negative.
+        let dh2 = ((a.a - b.a).powi(2) + (a.b - b.b).powi(2) - dc.powi(2)).max(0.0);
+
+        let sc = 1.0 + self.k1 * c1;
+        let sh = 1.0 + self.k2 * c1;
+        ((dl / self.kl).powi(2) + (dc / sc).powi(2) + dh2 / sh.powi(2)).sqrt()
+    }
+}
+
+/// The CMC l:c difference.
+///
+/// CMC is not symmetric, `a` is treated as the reference color.
+#[derive(Debug, Clone, Copy)]
+pub struct DiffCmc {
+    /// Weight of lightness differences.
+    pub l: f32,
+    /// Weight of chroma differences.
+    pub c: f32,
+}
+
+impl DiffCmc {
+    /// The 2:1 weighting, for judging whether a difference is acceptable.
+    pub const ACCEPTABILITY: Self = Self { l: 2.0, c: 1.0 };
+
+    /// The 1:1 weighting, for judging whether a difference is perceptible.
+    pub const PERCEPTIBILITY: Self = Self { l: 1.0, c: 1.0 };
+}
+
+impl<C: IntoColor<Lab>> Difference<C> for DiffCmc {
+    type Prepared = Lab;
+
+    fn prepare(&self, color: C) -> Lab {
+        color.into_color()
+    }
+
+    fn diff_prepared(&self, a: Lab, b: Lab) -> f32 {
+        let c1 = a.a.hypot(a.b);
+        let c2 = b.a.hypot(b.b);
+        let dl = a.l - b.l;
+        let dc = c1 - c2;
+        // Rounding errors can make this slightly negative.
+        let dh2 = ((a.a - b.a).powi(2) + (a.b - b.b).powi(2) - dc.powi(2)).max(0.0);
+
+        let sl = if a.l < 16.0 {
+            0.511
+        } else {
+            0.040975 * a.l / (1.0 + 0.01765 * a.l)
+        };
+        let sc = 0.0638 * c1 / (1.0 + 0.0131 * c1) + 0.638;
+        let f = (c1.powi(4) / (c1.powi(4) + 1900.0)).sqrt();
+        let h1 = a.b.atan2(a.a).to_degrees().rem_euclid(360.0);
+        let t = if (164.0..=345.0).contains(&h1) {
+            0.56 + (0.2 * (h1 + 168.0).to_radians().cos()).abs()
+        } else {
+            0.36 + (0.4 * (h1 + 35.0).to_radians().cos()).abs()
+        };
+        let sh = sc * (f * t + 1.0 - f);
+
+        ((dl / (self.l * sl)).powi(2) + (dc / (self.c * sc)).powi(2) + dh2 / sh.powi(2)).sqrt()
+    }
+}
+
+/// The "redmean" approximation of perceptual distance in srgb.
+///
+/// The euclidean distance in srgb, with the red and blue channels weighted
+/// depending on the average red level of both colors.
+pub struct DiffRedmean;
+
+impl<C: IntoColor<Srgb>> Difference<C> for DiffRedmean {
+    type Prepared = Srgb;
+
+    fn prepare(&self, color: C) -> Srgb {
+        color.into_color()
+    }
+
+    fn diff_prepared(&self, a: Srgb, b: Srgb) -> f32 {
+        let mean_red = (a.red + b.red) / 2.0;
+        let squared = (2.0 + mean_red) * (a.red - b.red).powi(2)
+            + 4.0 * (a.green - b.green).powi(2)
+            + (3.0 - mean_red) * (a.blue - b.blue).powi(2);
+        squared.sqrt()
+    }
+}
+
 pub struct DiffManhattan;
 
-impl<C: AsRef<[f32; 3]>> Difference<C> for DiffManhattan {
-    fn diff(a: C, b: C) -> f32 {
-        let [a1, a2, a3] = a.as_ref();
-        let [b1, b2, b3] = b.as_ref();
-        (a1 - b1).abs() + (a2 - b2).abs() + (a3 - b3).abs()
+impl<C: AsRef<[f32; 3]> + Copy + HueChannel + Sync> Difference<C> for DiffManhattan {
+    type Prepared = C;
+
+    fn prepare(&self, color: C) -> C {
+        color
+    }
+
+    fn diff_prepared(&self, a: C, b: C) -> f32 {
+        let [d1, d2, d3] = channel_deltas(a, b);
+        d1.abs() + d2.abs() + d3.abs()
     }
 }
 
@@ -79,211 +562,3191 @@ impl<C: AsRef<[f32; 3]>> Difference<C> for DiffManhattan {
 // Palette //
 /////////////
 
-pub struct Palette<C> {
-    colors: Vec<C>,
+/// Palettes with at least this many colors use a k-d tree for euclidean
+/// nearest color lookups. Smaller palettes are faster to scan linearly.
+const KD_TREE_MIN_COLORS: usize = 16;
+
+/// A k-d tree of palette color indices.
+///
+/// The tree is stored implicitly: The middle element of a slice is the root
+/// node splitting along the channel `depth % 3`, the elements before and after
+/// it are its subtrees.
+struct KdTree {
+    nodes: Vec<usize>,
 }
 
-impl<C> Palette<C> {
-    pub fn new(colors: Vec<C>) -> Self {
-        Self { colors }
+impl KdTree {
+    fn new<C: AsRef<[f32; 3]>>(colors: &[C]) -> Self {
+        let mut nodes = (0..colors.len()).collect::<Vec<_>>();
+        Self::build(colors, &mut nodes, 0);
+        Self { nodes }
+    }
+
+    fn build<C: AsRef<[f32; 3]>>(colors: &[C], nodes: &mut [usize], depth: usize) {
+        if nodes.len() <= 1 {
+            return;
+        }
+        let axis = depth % 3;
+        let mid = nodes.len() / 2;
+        nodes.select_nth_unstable_by(mid, |a, b| {
+            colors[*a].as_ref()[axis].total_cmp(&colors[*b].as_ref()[axis])
+        });
+        let (before, after) = nodes.split_at_mut(mid);
+        Self::build(colors, before, depth + 1);
+        Self::build(colors, &mut after[1..], depth + 1);
     }
 
-    fn nearest<D>(&self, to: C) -> C
+    /// Find the index of the nearest color, preferring lower indices on ties
+    /// like a linear scan would.
+    fn nearest<C, D>(&self, colors: &[C], difference: &D, to: C) -> Option<usize>
     where
+        C: AsRef<[f32; 3]>,
         C: Copy,
         D: Difference<C>,
     {
-        self.colors
-            .iter()
-            .copied()
-            .map(|c| (c, D::diff(c, to)))
-            .min_by(|(_, a), (_, b)| a.total_cmp(b))
-            .expect("palette was empty")
-            .0
+        let mut best = None;
+        Self::search(colors, difference, &self.nodes, to, 0, &mut best);
+        best.map(|(i, _)| i)
     }
-}
-
-////////////////
-// Algorithms //
-////////////////
-
-pub trait Algorithm<C, D> {
-    fn run(image: RgbaImage, palette: &Palette<C>) -> RgbaImage;
-}
-
-pub struct AlgoThreshold;
 
-impl<C, D> Algorithm<C, D> for AlgoThreshold
-where
-    Srgb: IntoColor<C>,
-    C: Copy,
-    C: IntoColor<Srgb>,
-    D: Difference<C>,
-{
-    fn run(mut image: RgbaImage, palette: &Palette<C>) -> RgbaImage {
-        for pixel in image.pixels_mut() {
-            let color: C = util::pixel_to_color(*pixel);
-            let color = palette.nearest::<D>(color);
-            util::update_pixel_with_color(pixel, color);
-        }
-        image
-    }
-}
-
-// TODO Fix probability calculation
-//
-// Choose probability for each color such that the expected value of a pixel is
-// its actual color (or as close as possible).
-//
-// We want to represent a pixel as a linear combination of palette colors with
-// factors in the range [0, 1] that sum up to 1. Then we can use those factors
-// as probabilities. This may not work for every palette.
-//
-// As a secondary optimization target, we might want to miminize the amount of
-// nonzero factors, if possible.
-pub struct AlgoRandom;
+    fn search<C, D>(
+        colors: &[C],
+        difference: &D,
+        nodes: &[usize],
+        to: C,
+        depth: usize,
+        best: &mut Option<(usize, f32)>,
+    ) where
+        C: AsRef<[f32; 3]>,
+        C: Copy,
+        D: Difference<C>,
+    {
+        if nodes.is_empty() {
+            return;
+        }
+        let axis = depth % 3;
+        let mid = nodes.len() / 2;
+        let i = nodes[mid];
 
-impl<C, D> Algorithm<C, D> for AlgoRandom
-where
-    Srgb: IntoColor<C>,
-    C: AsMut<[f32; 3]>,
-    C: Copy,
-    C: IntoColor<Srgb>,
-    D: Difference<C>,
-{
-    fn run(mut image: RgbaImage, palette: &Palette<C>) -> RgbaImage {
-        let mut rng = SmallRng::seed_from_u64(0);
-        let range_radius = 1.0;
+        let diff = difference.diff(colors[i], to);
+        let is_better = match *best {
+            None => true,
+            Some((best_i, best_diff)) => diff < best_diff || (diff == best_diff && i < best_i),
+        };
+        if is_better {
+            *best = Some((i, diff));
+        }
 
-        for pixel in image.pixels_mut() {
-            let mut color: C = util::pixel_to_color(*pixel);
-            color.as_mut()[0] += rng.gen_range(-range_radius..=range_radius);
-            color.as_mut()[1] += rng.gen_range(-range_radius..=range_radius);
-            color.as_mut()[2] += rng.gen_range(-range_radius..=range_radius);
-            let color = palette.nearest::<D>(color);
-            util::update_pixel_with_color(pixel, color);
+        let delta = to.as_ref()[axis] - colors[i].as_ref()[axis];
+        let (near, far) = if delta < 0.0 {
+            (&nodes[..mid], &nodes[mid + 1..])
+        } else {
+            (&nodes[mid + 1..], &nodes[..mid])
+        };
+        Self::search(colors, difference, near, to, depth + 1, best);
+        // The far side may still contain an equally near color with a lower
+        // index, hence the <= instead of <.
+        if best.is_some_and(|(_, best_diff)| delta.abs() <= best_diff) {
+            Self::search(colors, difference, far, to, depth + 1, best);
         }
-        image
     }
 }
 
-fn add<C: AsMut<[f32; 3]>>(mut a: C, mut b: C) -> C {
-    let [a1, a2, a3] = a.as_mut();
-    let [b1, b2, b3] = b.as_mut();
-    *a1 += *b1;
-    *a2 += *b2;
-    *a3 += *b3;
-    a
+#[derive(Debug)]
+pub enum PaletteError {
+    Empty,
 }
 
-fn sub<C: AsMut<[f32; 3]>>(mut a: C, mut b: C) -> C {
-    let [a1, a2, a3] = a.as_mut();
-    let [b1, b2, b3] = b.as_mut();
-    *a1 -= *b1;
-    *a2 -= *b2;
-    *a3 -= *b3;
-    a
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "dither requires at least one palette color"),
+        }
+    }
 }
 
-fn mul<C: AsMut<[f32; 3]>>(mut a: C, b: f32) -> C {
-    let [a1, a2, a3] = a.as_mut();
-    *a1 *= b;
-    *a2 *= b;
-    *a3 *= b;
-    a
+impl Error for PaletteError {}
+
+pub struct Palette<C> {
+    colors: Vec<C>,
+    tree: Option<KdTree>,
+    inverted: bool,
+    flag_out_of_gamut: bool,
 }
 
-fn diffuse_error<C>(image: &mut RgbaImage, error: C, x: u32, y: u32, dx: i32, dy: i32, factor: f32)
-where
-    C: AsMut<[f32; 3]>,
-    C: IntoColor<Srgb>,
-    Srgb: IntoColor<C>,
-{
-    if x == 0 && dx < 0 {
-        return;
+impl<C> Palette<C> {
+    /// Create a palette without checking it.
+    ///
+    /// Algorithms panic when dithering against an empty palette, see
+    /// [`Self::try_new`].
+    pub fn new(colors: Vec<C>) -> Self
+    where
+        C: AsRef<[f32; 3]>,
+    {
+        let tree = (colors.len() >= KD_TREE_MIN_COLORS).then(|| KdTree::new(&colors));
+        Self {
+            colors,
+            tree,
+            inverted: false,
+            flag_out_of_gamut: false,
+        }
     }
-    if y == 0 && dy < 0 {
-        return;
+
+    /// Create a palette, failing if it has no colors.
+    pub fn try_new(colors: Vec<C>) -> Result<Self, PaletteError>
+    where
+        C: AsRef<[f32; 3]>,
+    {
+        if colors.is_empty() {
+            return Err(PaletteError::Empty);
+        }
+        Ok(Self::new(colors))
     }
-    let x = (x as i32 + dx) as u32;
-    let y = (y as i32 + dy) as u32;
-    let Some(pixel) = image.get_pixel_mut_checked(x, y) else {
-        return;
-    };
-    let color: C = util::pixel_to_color(*pixel);
-    let color = add(color, mul(error, factor));
-    util::update_pixel_with_color(pixel, color);
-}
 
-pub struct AlgoFloydSteinberg;
+    /// Create a palette from srgb colors, converting them to the color space.
+    ///
+    /// Like [`Self::new`], this doesn't check the palette.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mark::dither::Palette;
+    /// use palette::{Oklab, Srgb};
+    ///
+    /// let colors = ["#1d2b53", "#ff004d", "#fff1e8"]
+    ///     .map(|hex| hex.parse::<Srgb<u8>>().unwrap());
+    /// let palette = Palette::<Oklab>::from_srgb(&colors);
+    ///
+    /// assert_eq!(palette.colors().len(), 3);
+    /// assert!(palette.colors()[2].l > palette.colors()[0].l);
+    /// ```
+    pub fn from_srgb(colors: &[Srgb<u8>]) -> Self
+    where
+        C: AsRef<[f32; 3]>,
+        Srgb: IntoColor<C>,
+    {
+        colors.iter().copied().collect()
+    }
 
-impl<C, D> Algorithm<C, D> for AlgoFloydSteinberg
-where
-    C: AsMut<[f32; 3]>,
-    C: Copy,
-    C: IntoColor<Srgb>,
-    D: Difference<C>,
-    Srgb: IntoColor<C>,
-{
-    fn run(mut image: RgbaImage, palette: &Palette<C>) -> RgbaImage {
-        for y in 0..image.height() {
-            for x in 0..image.width() {
-                let pixel = image.get_pixel(x, y);
-                let before: C = util::pixel_to_color(*pixel);
-                let after = palette.nearest::<D>(before);
-                let error = sub(before, after);
-
-                util::update_pixel_with_color(image.get_pixel_mut(x, y), after);
-                diffuse_error(&mut image, error, x, y, 1, 0, 7.0 / 16.0);
-                diffuse_error(&mut image, error, x, y, -1, 1, 3.0 / 16.0);
-                diffuse_error(&mut image, error, x, y, 0, 1, 5.0 / 16.0);
-                diffuse_error(&mut image, error, x, y, 1, 1, 1.0 / 16.0);
-            }
-        }
+    /// Like [`Self::from_srgb`], but drop repeated colors, keeping their first
+    /// occurrence.
+    ///
+    /// Colors are compared before converting them to the color space, so only
+    /// exact duplicates are removed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mark::dither::Palette;
+    /// use palette::{Oklab, Srgb};
+    ///
+    /// let colors = [Srgb::new(0, 0, 0), Srgb::new(255, 0, 0), Srgb::new(0, 0, 0)];
+    /// let palette = Palette::<Oklab>::deduplicated(&colors);
+    ///
+    /// assert_eq!(palette.colors().len(), 2);
+    /// ```
+    pub fn deduplicated(colors: &[Srgb<u8>]) -> Self
+    where
+        C: AsRef<[f32; 3]>,
+        Srgb: IntoColor<C>,
+    {
+        let mut seen = HashSet::new();
+        let unique = colors.iter().filter(|c| seen.insert(c.into_components()));
+        unique.copied().collect()
+    }
 
-        image
+    /// The colors of the palette.
+    pub fn colors(&self) -> &[C] {
+        &self.colors
     }
-}
 
-pub struct AlgoStucki;
+    /// Match colors with the farthest instead of the nearest palette color.
+    ///
+    /// This is an experimental effect that produces false-color output.
+    pub fn with_inverted_match(mut self, inverted: bool) -> Self {
+        self.inverted = inverted;
+        self
+    }
 
-impl<C, D> Algorithm<C, D> for AlgoStucki
-where
-    C: AsMut<[f32; 3]>,
-    C: Copy,
-    C: IntoColor<Srgb>,
-    D: Difference<C>,
-    Srgb: IntoColor<C>,
-{
-    fn run(mut image: RgbaImage, palette: &Palette<C>) -> RgbaImage {
-        for y in 0..image.height() {
-            for x in 0..image.width() {
-                let pixel = image.get_pixel(x, y);
-                let before: C = util::pixel_to_color(*pixel);
-                let after = palette.nearest::<D>(before);
-                let error = sub(before, after);
-
-                util::update_pixel_with_color(image.get_pixel_mut(x, y), after);
-
-                let base = 42.;
-
-                diffuse_error(&mut image, error, x, y, 1, 0, 8. / base);
-                diffuse_error(&mut image, error, x, y, 2, 0, 4. / base);
-
-                diffuse_error(&mut image, error, x, y, -2, 1, 2. / base);
-                diffuse_error(&mut image, error, x, y, -1, 1, 4. / base);
-                diffuse_error(&mut image, error, x, y, 0, 1, 8. / base);
-                diffuse_error(&mut image, error, x, y, 1, 1, 4. / base);
-                diffuse_error(&mut image, error, x, y, 2, 1, 2. / base);
-
-                diffuse_error(&mut image, error, x, y, -2, 2, 1. / base);
-                diffuse_error(&mut image, error, x, y, -1, 2, 2. / base);
-                diffuse_error(&mut image, error, x, y, 0, 2, 4. / base);
-                diffuse_error(&mut image, error, x, y, 1, 2, 2. / base);
-                diffuse_error(&mut image, error, x, y, 2, 2, 1. / base);
+    /// Output magenta instead of palette colors that lie outside the srgb
+    /// gamut and would otherwise be clamped.
+    pub fn with_out_of_gamut_flag(mut self, flag: bool) -> Self {
+        self.flag_out_of_gamut = flag;
+        self
+    }
+
+    /// Merge colors whose CIEDE2000 difference is below a threshold.
+    ///
+    /// Colors are grouped greedily in palette order: Each color joins the first
+    /// group whose first color is similar enough, or starts a new group. Each
+    /// group is replaced by the average of its colors. Hues are averaged along
+    /// the shorter way around the hue circle.
+    pub fn merge_similar(mut self, threshold: f32) -> Self
+    where
+        C: AsMut<[f32; 3]>,
+        C: AsRef<[f32; 3]>,
+        C: Copy,
+        C: HueChannel,
+        C: IntoColor<Lab>,
+    {
+        let mut groups: Vec<Vec<C>> = vec![];
+        for color in self.colors {
+            let similar = groups
+                .iter_mut()
+                .find(|group| DiffCiede2000.diff(group[0], color) < threshold);
+            match similar {
+                Some(group) => group.push(color),
+                None => groups.push(vec![color]),
             }
         }
 
-        image
+        self.colors = groups
+            .into_iter()
+            .map(|group| {
+                // Averaging the offsets from the first color keeps hues that
+                // lie on both sides of 0 degrees together.
+                let first = group[0];
+                let mut mean = first;
+                for color in &group {
+                    let deltas = channel_deltas(*color, first);
+                    for (m, d) in mean.as_mut().iter_mut().zip(deltas) {
+                        *m += d / group.len() as f32;
+                    }
+                }
+                if let Some(i) = C::HUE {
+                    mean.as_mut()[i] = mean.as_mut()[i].rem_euclid(360.0);
+                }
+                mean
+            })
+            .collect();
+        self.tree = (self.colors.len() >= KD_TREE_MIN_COLORS).then(|| KdTree::new(&self.colors));
+        self
+    }
+
+    /// Write a color chosen from the palette to a pixel.
+    fn write_pixel(&self, pixel: &mut Rgba<u8>, color: C)
+    where
+        C: IntoColor<Srgb>,
+    {
+        let srgb: Srgb = color.into_color();
+        if self.flag_out_of_gamut && !util::is_within_srgb_gamut(srgb) {
+            util::update_pixel_with_srgb(pixel, Srgb::new(1.0, 0.0, 1.0));
+        } else {
+            util::update_pixel_with_srgb(pixel, srgb);
+        }
+    }
+
+    /// Compute the difference between each palette color and a color.
+    pub fn differences<'a, D>(
+        &'a self,
+        difference: &'a D,
+        to: C,
+    ) -> impl Iterator<Item = (C, f32)> + 'a
+    where
+        C: Copy,
+        D: Difference<C>,
+    {
+        self.colors
+            .iter()
+            .copied()
+            .map(move |c| (c, difference.diff(c, to)))
+    }
+
+    /// Find the palette color nearest to a color.
+    ///
+    /// Respects [`Self::with_inverted_match`].
+    ///
+    /// This prepares every palette color for the difference on each call. To
+    /// look up many colors, [`Self::prepare`] the palette once and use
+    /// [`PreparedPalette::nearest`] instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the palette is empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mark::dither::{DiffEuclid, Palette};
+    /// use palette::Srgb;
+    ///
+    /// let palette = Palette::new(vec![
+    ///     Srgb::new(0.0, 0.0, 0.0),
+    ///     Srgb::new(1.0, 0.0, 0.0),
+    ///     Srgb::new(1.0, 1.0, 1.0),
+    /// ]);
+    ///
+    /// let nearest = palette.nearest(&DiffEuclid, Srgb::new(0.8, 0.1, 0.2));
+    /// assert_eq!(nearest, Srgb::new(1.0, 0.0, 0.0));
+    /// ```
+    pub fn nearest<D>(&self, difference: &D, to: C) -> C
+    where
+        C: AsRef<[f32; 3]>,
+        C: Copy,
+        D: Difference<C>,
+    {
+        self.prepare(difference).nearest(to)
+    }
+
+    /// Prepare the palette colors for repeated lookups.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mark::dither::{DiffCiede2000, Palette};
+    /// use palette::Lab;
+    ///
+    /// let palette = Palette::new(vec![Lab::new(0.0, 0.0, 0.0), Lab::new(100.0, 0.0, 0.0)]);
+    /// let prepared = palette.prepare(&DiffCiede2000);
+    ///
+    /// assert_eq!(prepared.nearest(Lab::new(20.0, 5.0, 0.0)), Lab::new(0.0, 0.0, 0.0));
+    /// assert_eq!(prepared.nearest(Lab::new(80.0, 5.0, 0.0)), Lab::new(100.0, 0.0, 0.0));
+    /// ```
+    pub fn prepare<'a, D>(&'a self, difference: &'a D) -> PreparedPalette<'a, C, D>
+    where
+        C: Copy,
+        D: Difference<C>,
+    {
+        let prepared = self.colors.iter().map(|c| difference.prepare(*c));
+        PreparedPalette {
+            palette: self,
+            difference,
+            prepared: prepared.collect(),
+        }
+    }
+}
+
+impl<C> FromIterator<Srgb<u8>> for Palette<C>
+where
+    C: AsRef<[f32; 3]>,
+    Srgb: IntoColor<C>,
+{
+    fn from_iter<T: IntoIterator<Item = Srgb<u8>>>(iter: T) -> Self {
+        let colors = iter.into_iter().map(|c| c.into_format().into_color());
+        Self::new(colors.collect())
+    }
+}
+
+/// A palette whose colors were prepared for a difference, see
+/// [`Palette::prepare`] and [`Difference::prepare`].
+pub struct PreparedPalette<'a, C, D: Difference<C>> {
+    palette: &'a Palette<C>,
+    difference: &'a D,
+    prepared: Vec<D::Prepared>,
+}
+
+impl<C, D: Difference<C>> PreparedPalette<'_, C, D> {
+    /// Find the palette color nearest to a color, see [`Palette::nearest`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the palette is empty.
+    pub fn nearest(&self, to: C) -> C
+    where
+        C: AsRef<[f32; 3]>,
+        C: Copy,
+    {
+        let Self {
+            palette,
+            difference,
+            ..
+        } = *self;
+
+        if let Some(tree) = &palette.tree {
+            if D::EUCLIDEAN && !palette.inverted {
+                let i = tree
+                    .nearest(&palette.colors, difference, to)
+                    .expect("palette was empty");
+                return palette.colors[i];
+            }
+        }
+
+        let to = difference.prepare(to);
+
+        // Two-color palettes are common enough to deserve a fast path.
+        if let ([a, b], [pa, pb]) = (&palette.colors[..], &self.prepared[..]) {
+            let a_is_nearer =
+                difference.diff_prepared(*pa, to) <= difference.diff_prepared(*pb, to);
+            return if a_is_nearer != palette.inverted {
+                *a
+            } else {
+                *b
+            };
+        }
+
+        let i = difference
+            .nearest_prepared(&self.prepared, to, palette.inverted)
+            .expect("palette was empty");
+        palette.colors[i]
+    }
+
+    /// Up to `n` of the nearest palette colors, nearest first.
+    ///
+    /// Respects [`Palette::with_inverted_match`].
+    fn nearest_few(&self, to: C, n: usize) -> Vec<C>
+    where
+        C: Copy,
+    {
+        let to = self.difference.prepare(to);
+        let mut differences = (self.palette.colors.iter())
+            .zip(&self.prepared)
+            .map(|(c, p)| (*c, self.difference.diff_prepared(*p, to)))
+            .collect::<Vec<_>>();
+        if self.palette.inverted {
+            differences.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        } else {
+            differences.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        }
+        differences.into_iter().take(n).map(|(c, _)| c).collect()
+    }
+}
+
+////////////////
+// Algorithms //
+////////////////
+
+/// The time limit of an algorithm was exceeded.
+#[derive(Debug)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dithering exceeded its time limit")
+    }
+}
+
+impl Error for TimedOut {}
+
+/// Lets the caller limit and observe a running algorithm.
+///
+/// Algorithms check in with their control at the start of every row.
+#[derive(Default, Clone)]
+pub struct Control {
+    deadline: Option<Instant>,
+    progress: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+impl fmt::Debug for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Control")
+            .field("deadline", &self.deadline)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl Control {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Abort the algorithm once the deadline has passed.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Call a function at the start of every row the algorithm processes.
+    ///
+    /// Algorithms that treat each pixel independently may process rows in
+    /// parallel and in any order, so the function only learns that one more
+    /// row was started.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::{
+    ///     atomic::{AtomicU32, Ordering},
+    ///     Arc,
+    /// };
+    ///
+    /// use image::RgbaImage;
+    /// use mark::dither::{AlgoFloydSteinberg, Algorithm, Control, DiffEuclid, Options, Palette};
+    /// use palette::Srgb;
+    ///
+    /// let rows = Arc::new(AtomicU32::new(0));
+    /// let counter = rows.clone();
+    /// let control = Control::new().with_progress(move || {
+    ///     counter.fetch_add(1, Ordering::Relaxed);
+    /// });
+    ///
+    /// let image = RgbaImage::new(4, 5);
+    /// let palette = Palette::new(vec![Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)]);
+    /// AlgoFloydSteinberg::run(image, &palette, &DiffEuclid, &Options::new(), &control).unwrap();
+    /// assert_eq!(rows.load(Ordering::Relaxed), 5);
+    /// ```
+    pub fn with_progress(mut self, progress: impl Fn() + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    fn row(&self) -> Result<(), TimedOut> {
+        if let Some(progress) = &self.progress {
+            progress();
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() > deadline => Err(TimedOut),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How algorithms treat the alpha channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    /// Leave the alpha channel as it is.
+    #[default]
+    Preserve,
+    /// Make pixels with at least this alpha opaque and all others transparent.
+    Threshold(u8),
+    /// Dither the alpha channel to fully opaque and fully transparent in the
+    /// same way as the color.
+    Dither,
+}
+
+/// The corner error diffusion algorithms start at.
+///
+/// The rows are processed away from this corner, and the kernels are mirrored
+/// so the error still flows towards pixels that haven't been processed yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffusionOrigin {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl DiffusionOrigin {
+    /// Whether the scan order is flipped horizontally and vertically.
+    fn flips(self) -> (bool, bool) {
+        match self {
+            Self::TopLeft => (false, false),
+            Self::TopRight => (true, false),
+            Self::BottomLeft => (false, true),
+            Self::BottomRight => (true, true),
+        }
+    }
+}
+
+/// Parameters that influence how algorithms dither.
+///
+/// Not every algorithm uses every parameter.
+#[derive(Debug, Clone)]
+pub struct Options {
+    diffused_channels: [bool; 3],
+    locked_colors: Vec<Srgb<u8>>,
+    skip_transparent: bool,
+    serpentine: bool,
+    diffusion_origin: DiffusionOrigin,
+    seed: u64,
+    coordinate_noise: bool,
+    noise_offset: [u32; 2],
+    noise_radius: [f32; 3],
+    cell_size: u32,
+    alpha_mode: AlphaMode,
+    linear_diffusion: bool,
+    error_clamp: bool,
+    mask: Option<GrayImage>,
+    diffusion_strength: f32,
+    threshold: Option<f32>,
+    source: Option<Rgba32FImage>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            diffused_channels: [true; 3],
+            locked_colors: vec![],
+            skip_transparent: false,
+            serpentine: false,
+            diffusion_origin: DiffusionOrigin::TopLeft,
+            seed: 0,
+            coordinate_noise: false,
+            noise_offset: [0, 0],
+            noise_radius: [0.0; 3],
+            cell_size: 8,
+            alpha_mode: AlphaMode::Preserve,
+            linear_diffusion: false,
+            error_clamp: false,
+            mask: None,
+            diffusion_strength: 1.0,
+            threshold: None,
+            source: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select which channels of the color space error diffusion algorithms
+    /// diffuse the error of.
+    ///
+    /// For example, diffusing only the first channel of Lab or Oklab dithers
+    /// the lightness while quantizing the chroma flatly.
+    pub fn with_diffused_channels(mut self, channels: [bool; 3]) -> Self {
+        self.diffused_channels = channels;
+        self
+    }
+
+    /// Pass pixels of these colors through unchanged.
+    ///
+    /// Locked pixels are compared against the input image before any error is
+    /// diffused. They neither receive nor diffuse any error.
+    pub fn with_locked_colors(mut self, colors: Vec<Srgb<u8>>) -> Self {
+        self.locked_colors = colors;
+        self
+    }
+
+    /// Let error diffusion algorithms scan every other row from right to left,
+    /// mirroring their kernel. This reduces directional artifacts.
+    pub fn with_serpentine(mut self, serpentine: bool) -> Self {
+        self.serpentine = serpentine;
+        self
+    }
+
+    /// Choose the corner error diffusion algorithms start at.
+    ///
+    /// Starting at the bottom right corner is the same as rotating the image
+    /// by 180 degrees, dithering it and rotating it back.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use image::{imageops, Rgba, RgbaImage};
+    /// use mark::dither::{
+    ///     AlgoFloydSteinberg, Algorithm, Control, DiffEuclid, DiffusionOrigin, Options, Palette,
+    /// };
+    /// use palette::Srgb;
+    ///
+    /// let image = RgbaImage::from_fn(9, 7, |x, y| Rgba([(x * 28) as u8, (y * 36) as u8, 128, 255]));
+    /// let palette = Palette::new(vec![Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)]);
+    /// let control = Control::new();
+    ///
+    /// let options = Options::new();
+    /// let rotated = imageops::rotate180(&image);
+    /// let rotated = AlgoFloydSteinberg::run(rotated, &palette, &DiffEuclid, &options, &control);
+    ///
+    /// let options = Options::new().with_diffusion_origin(DiffusionOrigin::BottomRight);
+    /// let flipped = AlgoFloydSteinberg::run(image, &palette, &DiffEuclid, &options, &control);
+    ///
+    /// assert_eq!(imageops::rotate180(&rotated.unwrap()), flipped.unwrap());
+    /// ```
+    pub fn with_diffusion_origin(mut self, origin: DiffusionOrigin) -> Self {
+        self.diffusion_origin = origin;
+        self
+    }
+
+    /// Seed the random number generator of random algorithms and shift the
+    /// matrix of ordered algorithms. Defaults to 0.
+    ///
+    /// The same seed reproduces the same output, as long as the algorithm
+    /// itself doesn't change. Different seeds repeat the matrix at different
+    /// positions, so large images dithered with different seeds don't share
+    /// the same visible tiling.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Derive the noise of random algorithms from the seed and each pixel's
+    /// coordinates instead of drawing it in scan order.
+    ///
+    /// A pixel then gets the same noise no matter the size of the image, so
+    /// cropped or tiled images match the whole image, see
+    /// [`Options::with_noise_offset`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use image::{imageops, Rgba, RgbaImage};
+    /// use mark::dither::{AlgoRandom, Algorithm, Control, DiffEuclid, Options, Palette};
+    /// use palette::Srgb;
+    ///
+    /// let image = RgbaImage::from_fn(16, 16, |x, y| Rgba([(x * 16) as u8, (y * 16) as u8, 0, 255]));
+    /// let palette = Palette::new(vec![
+    ///     Srgb::new(0.0, 0.0, 0.0),
+    ///     Srgb::new(1.0, 0.0, 0.0),
+    ///     Srgb::new(0.0, 1.0, 0.0),
+    ///     Srgb::new(1.0, 1.0, 0.0),
+    /// ]);
+    /// let options = Options::new().with_coordinate_noise(true);
+    /// let control = Control::new();
+    ///
+    /// let full = AlgoRandom::run(image.clone(), &palette, &DiffEuclid, &options, &control).unwrap();
+    ///
+    /// let crop = imageops::crop_imm(&image, 5, 3, 8, 8).to_image();
+    /// let options = options.with_noise_offset([5, 3]);
+    /// let crop = AlgoRandom::run(crop, &palette, &DiffEuclid, &options, &control).unwrap();
+    ///
+    /// assert_eq!(crop, imageops::crop_imm(&full, 5, 3, 8, 8).to_image());
+    /// ```
+    pub fn with_coordinate_noise(mut self, coordinate_noise: bool) -> Self {
+        self.coordinate_noise = coordinate_noise;
+        self
+    }
+
+    /// Set the position of the image's top left pixel in a larger image, used
+    /// by [`Options::with_coordinate_noise`] and to align the matrix of
+    /// ordered algorithms.
+    pub fn with_noise_offset(mut self, offset: [u32; 2]) -> Self {
+        self.noise_offset = offset;
+        self
+    }
+
+    /// A random number generator seeded from the seed of a stream and a
+    /// pixel's coordinates in the larger image.
+    fn coordinate_rng(&self, seed: u64, x: u32, y: u32) -> SmallRng {
+        let [ox, oy] = self.noise_offset;
+        let x = u64::from(ox.wrapping_add(x));
+        let y = u64::from(oy.wrapping_add(y));
+        SmallRng::seed_from_u64(mix(seed) ^ mix(y << 32 | x))
+    }
+
+    /// The shift of an `n`x`n` matrix of ordered algorithms, derived from the
+    /// seed.
+    fn pattern_phase(&self, n: u32) -> [u32; 2] {
+        let mut rng = SmallRng::seed_from_u64(self.seed);
+        [rng.gen_range(0..n), rng.gen_range(0..n)]
+    }
+
+    /// Set the radius of extra noise random algorithms add to each channel of
+    /// the color space before choosing a palette color.
+    ///
+    /// The noise is uniform and centered around zero, so it roughens the grain
+    /// without shifting the expected color. Defaults to no noise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any radius is negative or not finite.
+    pub fn with_noise_radius(mut self, radius: [f32; 3]) -> Self {
+        assert!(
+            radius.iter().all(|r| *r >= 0.0),
+            "noise radius must not be negative"
+        );
+        assert!(
+            radius.iter().all(|r| r.is_finite()),
+            "noise radius must be finite"
+        );
+        self.noise_radius = radius;
+        self
+    }
+
+    /// Set the distance in pixels between the dots of [`AlgoHalftone`].
+    /// Defaults to 8.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is zero or larger than [`MAX_CELL_SIZE`].
+    pub fn with_cell_size(mut self, size: u32) -> Self {
+        assert!(size > 0, "cell size must not be zero");
+        assert!(
+            size <= MAX_CELL_SIZE,
+            "cell size must be at most {MAX_CELL_SIZE}"
+        );
+        self.cell_size = size;
+        self
+    }
+
+    /// Choose how the alpha channel is treated.
+    ///
+    /// Error diffusion algorithms don't diffuse the color error of pixels that
+    /// end up fully transparent, since their color is invisible.
+    pub fn with_alpha_mode(mut self, mode: AlphaMode) -> Self {
+        self.alpha_mode = mode;
+        self
+    }
+
+    /// Compute and diffuse the error in linear srgb instead of the color space.
+    ///
+    /// Colors are still matched with the palette in the color space. Light
+    /// mixes linearly, so this keeps the average brightness of dithered
+    /// gradients correct. The diffused channels then refer to the linear srgb
+    /// channels.
+    pub fn with_linear_diffusion(mut self, linear: bool) -> Self {
+        self.linear_diffusion = linear;
+        self
+    }
+
+    /// Clamp colors to the valid range of the color space after they received
+    /// diffused error.
+    ///
+    /// Pixels are always stored in srgb, but colors far outside the range of
+    /// the color space can convert to srgb with a wrong hue, producing
+    /// sparkles near high contrast edges.
+    pub fn with_error_clamp(mut self, clamp: bool) -> Self {
+        self.error_clamp = clamp;
+        self
+    }
+
+    /// Scale the error that error diffusion algorithms pass on to neighbours.
+    ///
+    /// A strength of 1 diffuses the entire error, a strength of 0 diffuses
+    /// nothing and matches the threshold algorithm.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the strength is outside the range `0..=1`.
+    pub fn with_diffusion_strength(mut self, strength: f32) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&strength),
+            "diffusion strength must be between 0 and 1"
+        );
+        self.diffusion_strength = strength;
+        self
+    }
+
+    /// Split two-color palettes at a luma threshold instead of matching the
+    /// nearest color.
+    ///
+    /// Pixels whose luma is at least the threshold become the lighter palette
+    /// color, all others the darker one. The luma uses the Rec. 709 weights on
+    /// the gamma-encoded channels, so a threshold of 0.5 splits at mid gray.
+    /// Only the threshold algorithm uses this, and only for two-color palettes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the threshold is outside the range `0..=1`.
+    pub fn with_threshold(mut self, threshold: Option<f32>) -> Self {
+        assert!(
+            threshold.is_none_or(|t| (0.0..=1.0).contains(&t)),
+            "threshold must be between 0 and 1"
+        );
+        self.threshold = threshold;
+        self
+    }
+
+    /// Quantize an alpha value according to the alpha mode.
+    ///
+    /// The offset in the range `-0.5..0.5` is added before dithering the alpha
+    /// value, like algorithms offset colors.
+    fn quantize_alpha(&self, alpha: f32, offset: f32) -> f32 {
+        match self.alpha_mode {
+            AlphaMode::Preserve => alpha,
+            AlphaMode::Threshold(cutoff) if alpha >= cutoff as f32 => 255.0,
+            AlphaMode::Threshold(_) => 0.0,
+            AlphaMode::Dither if alpha + offset * 255.0 >= 127.5 => 255.0,
+            AlphaMode::Dither => 0.0,
+        }
+    }
+
+    /// Pass fully transparent pixels through unchanged.
+    ///
+    /// Like locked pixels, they neither receive nor diffuse any error, so no
+    /// error leaks into invisible areas.
+    pub fn with_skip_transparent(mut self, skip: bool) -> Self {
+        self.skip_transparent = skip;
+        self
+    }
+
+    /// Only dither pixels where the mask is white and pass all others through
+    /// unchanged.
+    ///
+    /// The mask should have the same size as the image, pixels outside of it
+    /// are dithered. Like locked pixels, masked out pixels neither receive nor
+    /// diffuse any error.
+    pub fn with_mask(mut self, mask: Option<GrayImage>) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    /// Read the colors to dither from a more precise version of the image, for
+    /// example one decoded from a 16 bit png.
+    ///
+    /// The source should have the same size as the image and channels in the
+    /// range `0..=1`, pixels outside of it are read from the image. The image
+    /// still provides the alpha channel and decides which pixels are skipped.
+    pub fn with_source(mut self, source: Option<Rgba32FImage>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// The color of a pixel, read from the source if there is one.
+    fn color_at<C>(&self, x: u32, y: u32, pixel: Rgba<u8>) -> C
+    where
+        Srgb: IntoColor<C>,
+    {
+        match self.source.as_ref().and_then(|s| s.get_pixel_checked(x, y)) {
+            Some(Rgba([r, g, b, _])) => Srgb::new(*r, *g, *b).into_color(),
+            None => util::pixel_to_color(pixel),
+        }
+    }
+
+    /// Whether a pixel is passed through unchanged, either because it is
+    /// locked, transparent or masked out.
+    fn is_skipped(&self, x: u32, y: u32, pixel: Rgba<u8>) -> bool {
+        let [r, g, b, a] = pixel.0;
+        let masked_out = self
+            .mask
+            .as_ref()
+            .and_then(|mask| mask.get_pixel_checked(x, y))
+            .is_some_and(|p| p.0[0] < 128);
+        masked_out
+            || (self.skip_transparent && a == 0)
+            || self.locked_colors.contains(&Srgb::new(r, g, b))
+    }
+
+    fn mask_error<C: AsMut<[f32; 3]>>(&self, mut error: C) -> C {
+        for (channel, diffused) in error.as_mut().iter_mut().zip(self.diffused_channels) {
+            if !diffused {
+                *channel = 0.0;
+            }
+        }
+        error
+    }
+}
+
+pub trait Algorithm<C, D> {
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut>;
+}
+
+/// Call a function with the coordinates of every pixel of an image.
+///
+/// Rows are processed in parallel if the `rayon` feature is enabled, so this
+/// is only suitable for algorithms treating each pixel independently.
+fn for_each_pixel<F>(image: &mut RgbaImage, control: &Control, f: F) -> Result<(), TimedOut>
+where
+    F: Fn(u32, u32, &mut Rgba<u8>) + Sync,
+{
+    let row_len = image.width() as usize * 4;
+    if row_len == 0 {
+        return Ok(());
+    }
+
+    let row = |(y, row): (usize, &mut [u8])| {
+        control.row()?;
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            f(x as u32, y as u32, Rgba::from_slice_mut(pixel));
+        }
+        Ok(())
+    };
+
+    #[cfg(feature = "rayon")]
+    return image.par_chunks_mut(row_len).enumerate().try_for_each(row);
+
+    #[cfg(not(feature = "rayon"))]
+    image.chunks_mut(row_len).enumerate().try_for_each(row)
+}
+
+/// Replace every pixel with its nearest palette color, without any dithering.
+pub struct AlgoThreshold;
+
+/// Another name for [`AlgoThreshold`], for remapping an image to a palette.
+pub type AlgoNearest = AlgoThreshold;
+
+/// The Rec. 709 luma of the gamma-encoded channels of a color.
+fn luma<C: IntoColor<Srgb>>(color: C) -> f32 {
+    let srgb: Srgb = color.into_color();
+    0.2126 * srgb.red + 0.7152 * srgb.green + 0.0722 * srgb.blue
+}
+
+impl<C, D> Algorithm<C, D> for AlgoThreshold
+where
+    Srgb: IntoColor<C>,
+    C: AsRef<[f32; 3]>,
+    C: Copy,
+    C: IntoColor<Srgb>,
+    C: Sync,
+    D: Difference<C>,
+    D: Sync,
+{
+    fn run(
+        mut image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        // The darker and lighter color of a two-color palette, if it should be
+        // split at the threshold.
+        let prepared = palette.prepare(difference);
+        let split = match (options.threshold, &palette.colors[..]) {
+            (Some(threshold), &[a, b]) => {
+                let (dark, light) = if luma(a) <= luma(b) { (a, b) } else { (b, a) };
+                let (dark, light) = if palette.inverted {
+                    (light, dark)
+                } else {
+                    (dark, light)
+                };
+                Some((threshold, dark, light))
+            }
+            _ => None,
+        };
+
+        for_each_pixel(&mut image, control, |x, y, pixel| {
+            if options.is_skipped(x, y, *pixel) {
+                return;
+            }
+            let color = match split {
+                Some((threshold, dark, light)) => {
+                    let is_light = luma(options.color_at::<Srgb>(x, y, *pixel)) >= threshold;
+                    if is_light {
+                        light
+                    } else {
+                        dark
+                    }
+                }
+                None => prepared.nearest(options.color_at(x, y, *pixel)),
+            };
+            palette.write_pixel(pixel, color);
+            pixel.0[3] = options.quantize_alpha(pixel.0[3] as f32, 0.0) as u8;
+        })?;
+        Ok(image)
+    }
+}
+
+/// Replace every pixel with a randomly chosen palette color, such that the
+/// expected color of a pixel is its actual color.
+///
+/// Each pixel is represented as a mix of its few nearest palette colors, with
+/// weights in the range [0, 1] that sum up to 1. Those weights are then used as
+/// the probabilities of choosing each color. Colors outside of what the palette
+/// can mix are approximated as closely as possible. Among equally close mixes,
+/// the one with the fewest colors wins.
+///
+/// Mixing happens in the coordinates of the color space, so the average over
+/// many pixels approaches the original color in that space. Hues are mixed
+/// along the shorter way around the hue circle.
+///
+/// # Example
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use mark::dither::{AlgoRandom, Algorithm, Control, DiffEuclid, Options, Palette};
+/// use palette::Srgb;
+///
+/// // 30% white, 30% red and 40% black
+/// let image = RgbaImage::from_pixel(128, 128, Rgba([153, 77, 77, 255]));
+/// let palette = Palette::new(vec![
+///     Srgb::new(0.0, 0.0, 0.0),
+///     Srgb::new(1.0, 1.0, 1.0),
+///     Srgb::new(1.0, 0.0, 0.0),
+/// ]);
+/// let (options, control) = (Options::new(), Control::new());
+/// let image = AlgoRandom::run(image, &palette, &DiffEuclid, &options, &control).unwrap();
+///
+/// let len = (image.width() * image.height()) as f32;
+/// for (i, expected) in [153.0, 77.0, 77.0].into_iter().enumerate() {
+///     let mean = image.pixels().map(|p| p.0[i] as f32).sum::<f32>() / len;
+///     assert!((mean - expected).abs() < 3.0, "channel {i}: {mean}");
+/// }
+/// ```
+pub struct AlgoRandom;
+
+/// How many of the nearest palette colors [`AlgoRandom`] mixes.
+///
+/// Four colors span a volume in a three-dimensional color space.
+const MIX_COLORS: usize = 4;
+
+/// Residuals within this distance count as equally close.
+const MIX_EPSILON: f64 = 1e-9;
+
+/// Mixed into the seed of [`AlgoRandom`]'s alpha generator so it draws
+/// different numbers than the color generator.
+const ALPHA_STREAM: u64 = 0xa1fa_a1fa_a1fa_a1fa;
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Weights summing up to 1 that mix the points as closely as possible into the
+/// target, or [`None`] if the points don't span a large enough space.
+///
+/// The weights may be negative if the target lies outside the points' hull.
+fn affine_weights(points: &[[f64; 3]], target: [f64; 3]) -> Option<[f64; MIX_COLORS]> {
+    let [p0, rest @ ..] = points else {
+        return None;
+    };
+    let relative = |p: [f64; 3]| [p[0] - p0[0], p[1] - p0[1], p[2] - p0[2]];
+    let dirs = rest.iter().map(|p| relative(*p)).collect::<Vec<_>>();
+    let target = relative(target);
+    let n = dirs.len();
+
+    // Normal equations of the least squares problem, solved by gaussian
+    // elimination with partial pivoting.
+    let mut rows = [[0.0; MIX_COLORS]; MIX_COLORS - 1];
+    for i in 0..n {
+        for j in 0..n {
+            rows[i][j] = dot(dirs[i], dirs[j]);
+        }
+        rows[i][n] = dot(dirs[i], target);
+    }
+    let scale = (0..n).map(|i| rows[i][i]).fold(0.0, f64::max);
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|a, b| rows[*a][col].abs().total_cmp(&rows[*b][col].abs()))
+            .expect("range is not empty");
+        if rows[pivot][col].abs() <= MIX_EPSILON * scale.max(MIX_EPSILON) {
+            return None;
+        }
+        rows.swap(col, pivot);
+        for row in col + 1..n {
+            let factor = rows[row][col] / rows[col][col];
+            let pivot_row = rows[col];
+            for (a, b) in rows[row][col..=n].iter_mut().zip(&pivot_row[col..=n]) {
+                *a -= factor * b;
+            }
+        }
+    }
+
+    let mut weights = [0.0; MIX_COLORS];
+    for i in (0..n).rev() {
+        let known = (i + 1..n).map(|k| rows[i][k] * weights[k + 1]).sum::<f64>();
+        weights[i + 1] = (rows[i][n] - known) / rows[i][i];
+    }
+    weights[0] = 1.0 - weights[1..].iter().sum::<f64>();
+    Some(weights)
+}
+
+/// Nonnegative weights summing up to 1 that mix the points as closely as
+/// possible into the target, preferring mixes of fewer points.
+fn mix_weights(points: &[[f64; 3]], target: [f64; 3]) -> [f64; MIX_COLORS] {
+    let mut best = (f64::INFINITY, [0.0; MIX_COLORS]);
+    let mut subset = Vec::with_capacity(MIX_COLORS);
+    for size in 1..=points.len() {
+        for mask in 0_u32..1 << points.len() {
+            if mask.count_ones() as usize != size {
+                continue;
+            }
+            let indices = (0..points.len()).filter(|i| mask & 1 << i != 0);
+            subset.clear();
+            subset.extend(indices.clone().map(|i| points[i]));
+            let Some(weights) = affine_weights(&subset, target) else {
+                continue;
+            };
+            if weights[..size].iter().any(|w| *w < -MIX_EPSILON) {
+                continue;
+            }
+
+            let mut full = [0.0; MIX_COLORS];
+            let mut mixed = [0.0; 3];
+            for (w, i) in weights.iter().zip(indices) {
+                full[i] = w.max(0.0);
+                for (m, p) in mixed.iter_mut().zip(points[i]) {
+                    *m += full[i] * p;
+                }
+            }
+            let residual = [0, 1, 2].map(|c| mixed[c] - target[c]);
+            let residual = dot(residual, residual);
+            if residual < best.0 - MIX_EPSILON {
+                best = (residual, full);
+            }
+        }
+    }
+    best.1
+}
+
+/// Scramble the bits of a number, the finalizer of splitmix64.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl<C, D> Algorithm<C, D> for AlgoRandom
+where
+    Srgb: IntoColor<C>,
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+{
+    fn run(
+        mut image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let mut rng = SmallRng::seed_from_u64(options.seed);
+        // A separate generator keeps the colors independent of the alpha mode,
+        // and its own seed keeps the alpha independent of the colors.
+        let alpha_seed = mix(options.seed ^ ALPHA_STREAM);
+        let mut alpha_rng = SmallRng::seed_from_u64(alpha_seed);
+        let [r1, r2, r3] = options.noise_radius;
+        let prepared = palette.prepare(difference);
+        let coords = |c: C| c.as_ref().map(f64::from);
+        // Hues are unwrapped to lie within 180 degrees of the pixel's hue, so
+        // mixing happens along the shorter way around the hue circle.
+        let unwrapped = |c: C, around: C| {
+            let deltas = channel_deltas(c, around);
+            let around = coords(around);
+            [0, 1, 2].map(|i| around[i] + f64::from(deltas[i]))
+        };
+
+        for (y, row) in image.rows_mut().enumerate() {
+            control.row()?;
+            for (x, pixel) in row.enumerate() {
+                if options.is_skipped(x as u32, y as u32, *pixel) {
+                    continue;
+                }
+                if options.coordinate_noise {
+                    rng = options.coordinate_rng(options.seed, x as u32, y as u32);
+                    alpha_rng = options.coordinate_rng(alpha_seed, x as u32, y as u32);
+                }
+                let mut color: C = options.color_at(x as u32, y as u32, *pixel);
+                color.as_mut()[0] += rng.gen_range(-r1..=r1);
+                color.as_mut()[1] += rng.gen_range(-r2..=r2);
+                color.as_mut()[2] += rng.gen_range(-r3..=r3);
+
+                let candidates = prepared.nearest_few(color, MIX_COLORS);
+                let points = (candidates.iter())
+                    .map(|c| unwrapped(*c, color))
+                    .collect::<Vec<_>>();
+                let weights = mix_weights(&points, coords(color));
+                let mut choice = rng.gen_range(0.0..weights.iter().sum::<f64>());
+                let i = (weights.iter())
+                    .position(|w| {
+                        choice -= w;
+                        choice < 0.0
+                    })
+                    .unwrap_or_else(|| weights.iter().rposition(|w| *w > 0.0).unwrap_or(0));
+                palette.write_pixel(pixel, candidates[i]);
+                if options.alpha_mode != AlphaMode::Preserve {
+                    let offset = alpha_rng.gen_range(-0.5..0.5);
+                    pixel.0[3] = options.quantize_alpha(pixel.0[3] as f32, offset) as u8;
+                }
+            }
+        }
+        Ok(image)
+    }
+}
+
+/// Ordered dithering with an `N`x`N` Bayer matrix, where `N` is a power of
+/// two.
+///
+/// Each pixel is offset by its threshold from the matrix before matching it
+/// with the palette. The offset is scaled per channel to the average distance
+/// between palette colors along that channel.
+pub struct AlgoBayer<const N: u32>;
+
+impl<const N: u32> AlgoBayer<N> {
+    /// The index of a cell in the Bayer matrix, in the range `0..N*N`.
+    fn index(size: u32, x: u32, y: u32) -> u32 {
+        if size <= 1 {
+            return 0;
+        }
+        let half = size / 2;
+        let quadrant = match (x / half, y / half) {
+            (0, 0) => 0,
+            (1, 1) => 1,
+            (1, 0) => 2,
+            _ => 3,
+        };
+        4 * Self::index(half, x % half, y % half) + quadrant
+    }
+
+    /// The threshold of a pixel, in the range `-0.5..0.5`.
+    fn threshold(x: u32, y: u32) -> f32 {
+        let index = Self::index(N, x % N, y % N) as f32;
+        (index + 0.5) / (N * N) as f32 - 0.5
+    }
+}
+
+/// Ordered dithering with the 2x2 Bayer matrix, whose thresholds are
+/// `[0, 2; 3, 1] / 4`.
+///
+/// A flat color halfway between two palette colors becomes the classic
+/// checkerboard.
+///
+/// # Example
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use mark::dither::{AlgoCheckerboard, Algorithm, Control, DiffEuclid, Options, Palette};
+/// use palette::Srgb;
+///
+/// let image = RgbaImage::from_pixel(6, 4, Rgba([128, 128, 128, 255]));
+/// let palette = Palette::new(vec![Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)]);
+/// let (options, control) = (Options::new(), Control::new());
+/// let result = AlgoCheckerboard::run(image, &palette, &DiffEuclid, &options, &control).unwrap();
+///
+/// // The seed decides which of the two colors comes first.
+/// let checkerboard = |first| {
+///     RgbaImage::from_fn(6, 4, |x, y| match (x + y + first) % 2 {
+///         0 => Rgba([0, 0, 0, 255]),
+///         _ => Rgba([255, 255, 255, 255]),
+///     })
+/// };
+/// assert!(result == checkerboard(0) || result == checkerboard(1));
+/// ```
+pub type AlgoCheckerboard = AlgoBayer<2>;
+
+/// Ordered dithering with a clustered-dot matrix, for the look of a printed
+/// halftone screen.
+///
+/// Instead of scattering them, the matrix grows the pixels of each palette
+/// color as contiguous dots. The dots form a grid rotated by 45° whose spacing
+/// is set by [`Options::with_cell_size`]. Otherwise, this works like
+/// [`AlgoBayer`].
+///
+/// # Example
+///
+/// ```
+/// use image::{imageops, Rgba, RgbaImage};
+/// use mark::dither::{AlgoHalftone, Algorithm, Control, DiffEuclid, Options, Palette};
+/// use palette::Srgb;
+///
+/// let image = RgbaImage::from_pixel(24, 24, Rgba([100, 100, 100, 255]));
+/// let palette = Palette::new(vec![Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)]);
+/// let options = Options::new().with_cell_size(6);
+/// let control = Control::new();
+///
+/// let dots = AlgoHalftone::run(image.clone(), &palette, &DiffEuclid, &options, &control).unwrap();
+/// let again = AlgoHalftone::run(image.clone(), &palette, &DiffEuclid, &options, &control).unwrap();
+/// assert_eq!(dots, again);
+///
+/// // The pattern repeats every cell.
+/// let tile = imageops::crop_imm(&dots, 0, 0, 6, 6).to_image();
+/// assert_eq!(imageops::crop_imm(&dots, 6, 12, 6, 6).to_image(), tile);
+///
+/// // Crops get the same pattern as the whole image.
+/// let crop = imageops::crop_imm(&image, 5, 3, 8, 8).to_image();
+/// let options = options.with_noise_offset([5, 3]);
+/// let crop = AlgoHalftone::run(crop, &palette, &DiffEuclid, &options, &control).unwrap();
+/// assert_eq!(crop, imageops::crop_imm(&dots, 5, 3, 8, 8).to_image());
+/// ```
+pub struct AlgoHalftone;
+
+/// The largest cell size of [`AlgoHalftone`], see [`Options::with_cell_size`].
+pub const MAX_CELL_SIZE: u32 = 256;
+
+impl AlgoHalftone {
+    /// The thresholds of a cell, row by row, in the range `-0.5..0.5`.
+    ///
+    /// The spot function has peaks at the corners and the center of the cell
+    /// and valleys halfway along its edges. Pixels nearest the peaks get the
+    /// lowest thresholds, so dots grow outwards from there.
+    fn thresholds(size: u32) -> Vec<f32> {
+        let spot = |i: u32| {
+            let u = ((i % size) as f32 + 0.5) / size as f32;
+            let v = ((i / size) as f32 + 0.5) / size as f32;
+            (TAU * u).cos() * (TAU * v).cos()
+        };
+        let cells = size.checked_mul(size).expect("cell size is too large");
+        let mut order = (0..cells).collect::<Vec<_>>();
+        order.sort_by(|a, b| spot(*b).total_cmp(&spot(*a)));
+
+        let mut thresholds = vec![0.0; order.len()];
+        for (rank, i) in order.into_iter().enumerate() {
+            thresholds[i as usize] = (rank as f32 + 0.5) / cells as f32 - 0.5;
+        }
+        thresholds
+    }
+}
+
+impl<C, D> Algorithm<C, D> for AlgoHalftone
+where
+    Srgb: IntoColor<C>,
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Copy,
+    C: IntoColor<Srgb>,
+    C: Sync,
+    D: Difference<C>,
+    D: Sync,
+{
+    fn run(
+        mut image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let size = options.cell_size;
+        let thresholds = Self::thresholds(size);
+        let spread = palette_spread(palette);
+        let prepared = palette.prepare(difference);
+        let [px, py] = options.pattern_phase(size);
+        for_each_pixel(&mut image, control, |x, y, pixel| {
+            if options.is_skipped(x, y, *pixel) {
+                return;
+            }
+            let [ox, oy] = options.noise_offset;
+            let x_in_cell = (x.wrapping_add(ox) % size + px) % size;
+            let y_in_cell = (y.wrapping_add(oy) % size + py) % size;
+            let threshold = thresholds[(y_in_cell * size + x_in_cell) as usize];
+            let mut color: C = options.color_at(x, y, *pixel);
+            for (channel, spread) in color.as_mut().iter_mut().zip(spread) {
+                *channel += threshold * spread;
+            }
+            let color = prepared.nearest(color);
+            palette.write_pixel(pixel, color);
+            pixel.0[3] = options.quantize_alpha(pixel.0[3] as f32, threshold) as u8;
+        })?;
+        Ok(image)
+    }
+}
+
+fn palette_spread<C: AsRef<[f32; 3]>>(palette: &Palette<C>) -> [f32; 3] {
+    let steps = palette.colors.len().saturating_sub(1).max(1) as f32;
+    [0, 1, 2].map(|i| {
+        let channel = palette.colors.iter().map(|c| c.as_ref()[i]);
+        let min = channel.clone().fold(f32::INFINITY, f32::min);
+        let max = channel.fold(f32::NEG_INFINITY, f32::max);
+        (max - min).max(0.0) / steps
+    })
+}
+
+impl<C, D, const N: u32> Algorithm<C, D> for AlgoBayer<N>
+where
+    Srgb: IntoColor<C>,
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Copy,
+    C: IntoColor<Srgb>,
+    C: Sync,
+    D: Difference<C>,
+    D: Sync,
+{
+    fn run(
+        mut image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let spread = palette_spread(palette);
+        let prepared = palette.prepare(difference);
+        let [px, py] = options.pattern_phase(N);
+        for_each_pixel(&mut image, control, |x, y, pixel| {
+            if options.is_skipped(x, y, *pixel) {
+                return;
+            }
+            let [ox, oy] = options.noise_offset;
+            let x_in_matrix = x.wrapping_add(ox) % N + px;
+            let y_in_matrix = y.wrapping_add(oy) % N + py;
+            let threshold = Self::threshold(x_in_matrix, y_in_matrix);
+            let mut color: C = options.color_at(x, y, *pixel);
+            for (channel, spread) in color.as_mut().iter_mut().zip(spread) {
+                *channel += threshold * spread;
+            }
+            let color = prepared.nearest(color);
+            palette.write_pixel(pixel, color);
+            pixel.0[3] = options.quantize_alpha(pixel.0[3] as f32, threshold) as u8;
+        })?;
+        Ok(image)
+    }
+}
+
+fn add<C: AsMut<[f32; 3]>>(mut a: C, mut b: C) -> C {
+    let [a1, a2, a3] = a.as_mut();
+    let [b1, b2, b3] = b.as_mut();
+    *a1 += *b1;
+    *a2 += *b2;
+    *a3 += *b3;
+    a
+}
+
+/// Subtract two colors channel by channel.
+///
+/// The hue difference is wrapped so the error takes the short way around the
+/// hue circle.
+fn color_error<C>(before: C, after: C) -> C
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Copy,
+    C: HueChannel,
+{
+    let mut error = before;
+    *error.as_mut() = channel_deltas(before, after);
+    error
+}
+
+fn mul<C: AsMut<[f32; 3]>>(mut a: C, b: f32) -> C {
+    let [a1, a2, a3] = a.as_mut();
+    *a1 *= b;
+    *a2 *= b;
+    *a3 *= b;
+    a
+}
+
+fn neighbour_mut(image: &mut RgbaImage, x: u32, y: u32, dx: i32, dy: i32) -> Option<&mut Rgba<u8>> {
+    let x = x.checked_add_signed(dx)?;
+    let y = y.checked_add_signed(dy)?;
+    image.get_pixel_mut_checked(x, y)
+}
+
+/// The colors of an image, converted to a color space once.
+///
+/// Error diffusion accumulates the error of several pixels in each neighbour.
+/// Keeping the colors around avoids converting each neighbour from and to srgb
+/// every time it receives some error.
+struct ColorBuffer<C> {
+    width: u32,
+    height: u32,
+    colors: Vec<C>,
+}
+
+impl<C> ColorBuffer<C> {
+    fn new(image: &RgbaImage, options: &Options) -> Self
+    where
+        Srgb: IntoColor<C>,
+    {
+        let colors = image.enumerate_pixels();
+        Self {
+            width: image.width(),
+            height: image.height(),
+            colors: colors.map(|(x, y, p)| options.color_at(x, y, *p)).collect(),
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> C
+    where
+        C: Copy,
+    {
+        self.colors[y as usize * self.width as usize + x as usize]
+    }
+
+    fn neighbour_mut(&mut self, x: u32, y: u32, dx: i32, dy: i32) -> Option<&mut C> {
+        let x = x.checked_add_signed(dx).filter(|x| *x < self.width)?;
+        let y = y.checked_add_signed(dy).filter(|y| *y < self.height)?;
+        self.colors
+            .get_mut(y as usize * self.width as usize + x as usize)
+    }
+}
+
+/// Add an already weighted error to a neighbour, optionally clamping the
+/// result.
+fn diffuse_error<C>(
+    buffer: &mut ColorBuffer<C>,
+    error: C,
+    x: u32,
+    y: u32,
+    dx: i32,
+    dy: i32,
+    clamp: bool,
+) where
+    C: AsMut<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+{
+    let Some(color) = buffer.neighbour_mut(x, y, dx, dy) else {
+        return;
+    };
+    let sum = add(*color, error);
+    *color = if clamp { sum.clamp() } else { sum };
+}
+
+/// Like [`diffuse_error`], but for an error in linear srgb.
+fn diffuse_linear_error<C>(
+    buffer: &mut ColorBuffer<C>,
+    error: LinSrgb,
+    x: u32,
+    y: u32,
+    dx: i32,
+    dy: i32,
+    clamp: bool,
+) where
+    C: Copy,
+    C: IntoColor<Srgb>,
+    Srgb: IntoColor<C>,
+{
+    let Some(color) = buffer.neighbour_mut(x, y, dx, dy) else {
+        return;
+    };
+    let srgb: Srgb = (*color).into_color();
+    let sum = add(srgb.into_linear(), error);
+    let sum = if clamp { sum.clamp() } else { sum };
+    *color = Srgb::from_linear(sum).into_color();
+}
+
+fn diffuse_alpha_error(
+    image: &mut RgbaImage,
+    error: f32,
+    x: u32,
+    y: u32,
+    dx: i32,
+    dy: i32,
+    factor: f32,
+) {
+    let Some(pixel) = neighbour_mut(image, x, y, dx, dy) else {
+        return;
+    };
+    let alpha = pixel.0[3] as f32 + error * factor;
+    pixel.0[3] = alpha.round().clamp(0.0, 255.0) as u8;
+}
+
+/// The skipped pixels of an image before any error was diffused into it.
+struct Skipped {
+    width: u32,
+    pixels: Vec<Option<Rgba<u8>>>,
+}
+
+impl Skipped {
+    fn new(image: &RgbaImage, options: &Options) -> Self {
+        let pixels = if options.locked_colors.is_empty()
+            && !options.skip_transparent
+            && options.mask.is_none()
+        {
+            vec![]
+        } else {
+            let skipped = |(x, y, p): (u32, u32, &Rgba<u8>)| {
+                Some(*p).filter(|p| options.is_skipped(x, y, *p))
+            };
+            image.enumerate_pixels().map(skipped).collect()
+        };
+        Self {
+            width: image.width(),
+            pixels,
+        }
+    }
+
+    /// The original pixel, if it is skipped.
+    fn pixel(&self, x: u32, y: u32) -> Option<Rgba<u8>> {
+        let i = y as usize * self.width as usize + x as usize;
+        self.pixels.get(i).copied().flatten()
+    }
+}
+
+/// The neighbours that receive error as `(dx, dy, numerator)` entries, and the
+/// divisor of the numerators, see [`diffuse_with_kernel`].
+type Kernel = (&'static [(i32, i32, f32)], f32);
+
+/// Dither an image by diffusing the error of each pixel to its neighbours.
+///
+/// Each `(dx, dy, numerator)` entry of the kernel receives `numerator /
+/// divisor` of the error.
+fn diffuse_with_kernel<C, D>(
+    mut image: RgbaImage,
+    palette: &Palette<C>,
+    difference: &D,
+    options: &Options,
+    control: &Control,
+    kernel: &[(i32, i32, f32)],
+    divisor: f32,
+) -> Result<RgbaImage, TimedOut>
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    let skipped = Skipped::new(&image, options);
+    let mut buffer = ColorBuffer::<C>::new(&image, options);
+    let prepared = palette.prepare(difference);
+    let clamp = options.error_clamp;
+    let (flip_x, flip_y) = options.diffusion_origin.flips();
+    for row in 0..image.height() {
+        control.row()?;
+        let y = if flip_y {
+            image.height() - 1 - row
+        } else {
+            row
+        };
+        let reversed = flip_x != (options.serpentine && row % 2 == 1);
+        for i in 0..image.width() {
+            let x = if reversed { image.width() - 1 - i } else { i };
+            if let Some(pixel) = skipped.pixel(x, y) {
+                image.put_pixel(x, y, pixel);
+                continue;
+            }
+            let pixel = image.get_pixel(x, y);
+            let before = buffer.get(x, y);
+            let after = prepared.nearest(before);
+            let error = options.mask_error(color_error(before, after));
+            let alpha_before = pixel.0[3] as f32;
+            let alpha_after = options.quantize_alpha(alpha_before, 0.0);
+            let alpha_error = alpha_before - alpha_after;
+
+            let pixel = image.get_pixel_mut(x, y);
+            palette.write_pixel(pixel, after);
+            pixel.0[3] = alpha_after as u8;
+            let linear_error = options.linear_diffusion.then(|| {
+                let before_linear = IntoColor::<Srgb>::into_color(before).into_linear();
+                let after_linear = util::pixel_to_color::<LinSrgb>(*pixel);
+                options.mask_error(color_error(before_linear, after_linear))
+            });
+
+            // The color of pixels that were made transparent is invisible.
+            let diffuse_color = options.alpha_mode == AlphaMode::Preserve || alpha_after > 0.0;
+            let diffuse_alpha = options.alpha_mode == AlphaMode::Dither;
+            for (dx, dy, numerator) in kernel {
+                let dx = if reversed { -dx } else { *dx };
+                let dy = if flip_y { -dy } else { *dy };
+                let factor = numerator / divisor * options.diffusion_strength;
+                if diffuse_color {
+                    match linear_error {
+                        Some(error) => {
+                            let error = mul(error, factor);
+                            diffuse_linear_error(&mut buffer, error, x, y, dx, dy, clamp)
+                        }
+                        None => {
+                            let error = mul(error, factor);
+                            diffuse_error(&mut buffer, error, x, y, dx, dy, clamp)
+                        }
+                    }
+                }
+                if diffuse_alpha {
+                    diffuse_alpha_error(&mut image, alpha_error, x, y, dx, dy, factor);
+                }
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+pub struct AlgoFloydSteinberg;
+
+impl AlgoFloydSteinberg {
+    const KERNEL: Kernel = (&[(1, 0, 7.), (-1, 1, 3.), (0, 1, 5.), (1, 1, 1.)], 16.0);
+}
+
+impl<C, D> Algorithm<C, D> for AlgoFloydSteinberg
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let (kernel, divisor) = Self::KERNEL;
+        diffuse_with_kernel(
+            image, palette, difference, options, control, kernel, divisor,
+        )
+    }
+}
+
+/// Floyd-Steinberg without any vertical diffusion.
+///
+/// The entire error is diffused to the next pixel in the same row, which
+/// produces horizontal streaks.
+pub struct AlgoFloydSteinberg1D;
+
+impl AlgoFloydSteinberg1D {
+    const KERNEL: Kernel = (&[(1, 0, 1.)], 1.0);
+}
+
+impl<C, D> Algorithm<C, D> for AlgoFloydSteinberg1D
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let (kernel, divisor) = Self::KERNEL;
+        diffuse_with_kernel(
+            image, palette, difference, options, control, kernel, divisor,
+        )
+    }
+}
+
+pub struct AlgoStucki;
+
+impl AlgoStucki {
+    const KERNEL: Kernel = (
+        &[
+            (1, 0, 8.),
+            (2, 0, 4.),
+            (-2, 1, 2.),
+            (-1, 1, 4.),
+            (0, 1, 8.),
+            (1, 1, 4.),
+            (2, 1, 2.),
+            (-2, 2, 1.),
+            (-1, 2, 2.),
+            (0, 2, 4.),
+            (1, 2, 2.),
+            (2, 2, 1.),
+        ],
+        42.0,
+    );
+}
+
+impl<C, D> Algorithm<C, D> for AlgoStucki
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let (kernel, divisor) = Self::KERNEL;
+        diffuse_with_kernel(
+            image, palette, difference, options, control, kernel, divisor,
+        )
+    }
+}
+
+/// Jarvis-Judice-Ninke dithering.
+///
+/// Spreads the error over a larger area than Floyd-Steinberg, which reduces
+/// directional artifacts in gradients.
+pub struct AlgoJarvis;
+
+impl AlgoJarvis {
+    const KERNEL: Kernel = (
+        &[
+            (1, 0, 7.),
+            (2, 0, 5.),
+            (-2, 1, 3.),
+            (-1, 1, 5.),
+            (0, 1, 7.),
+            (1, 1, 5.),
+            (2, 1, 3.),
+            (-2, 2, 1.),
+            (-1, 2, 3.),
+            (0, 2, 5.),
+            (1, 2, 3.),
+            (2, 2, 1.),
+        ],
+        48.0,
+    );
+}
+
+impl<C, D> Algorithm<C, D> for AlgoJarvis
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let (kernel, divisor) = Self::KERNEL;
+        diffuse_with_kernel(
+            image, palette, difference, options, control, kernel, divisor,
+        )
+    }
+}
+
+/// Burkes dithering, a cheaper two-row variant of Stucki.
+pub struct AlgoBurkes;
+
+impl AlgoBurkes {
+    const KERNEL: Kernel = (
+        &[
+            (1, 0, 8.),
+            (2, 0, 4.),
+            (-2, 1, 2.),
+            (-1, 1, 4.),
+            (0, 1, 8.),
+            (1, 1, 4.),
+            (2, 1, 2.),
+        ],
+        32.0,
+    );
+}
+
+impl<C, D> Algorithm<C, D> for AlgoBurkes
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let (kernel, divisor) = Self::KERNEL;
+        diffuse_with_kernel(
+            image, palette, difference, options, control, kernel, divisor,
+        )
+    }
+}
+
+/// Sierra dithering, also known as Sierra-3.
+pub struct AlgoSierra;
+
+impl AlgoSierra {
+    const KERNEL: Kernel = (
+        &[
+            (1, 0, 5.),
+            (2, 0, 3.),
+            (-2, 1, 2.),
+            (-1, 1, 4.),
+            (0, 1, 5.),
+            (1, 1, 4.),
+            (2, 1, 2.),
+            (-1, 2, 2.),
+            (0, 2, 3.),
+            (1, 2, 2.),
+        ],
+        32.0,
+    );
+}
+
+impl<C, D> Algorithm<C, D> for AlgoSierra
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let (kernel, divisor) = Self::KERNEL;
+        diffuse_with_kernel(
+            image, palette, difference, options, control, kernel, divisor,
+        )
+    }
+}
+
+/// Two-row Sierra dithering, also known as Sierra-2.
+pub struct AlgoSierraTwoRow;
+
+impl AlgoSierraTwoRow {
+    const KERNEL: Kernel = (
+        &[
+            (1, 0, 4.),
+            (2, 0, 3.),
+            (-2, 1, 1.),
+            (-1, 1, 2.),
+            (0, 1, 3.),
+            (1, 1, 2.),
+            (2, 1, 1.),
+        ],
+        16.0,
+    );
+}
+
+impl<C, D> Algorithm<C, D> for AlgoSierraTwoRow
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let (kernel, divisor) = Self::KERNEL;
+        diffuse_with_kernel(
+            image, palette, difference, options, control, kernel, divisor,
+        )
+    }
+}
+
+/// Sierra-Lite dithering, a cheap approximation of Floyd-Steinberg.
+pub struct AlgoSierraLite;
+
+impl AlgoSierraLite {
+    const KERNEL: Kernel = (&[(1, 0, 2.), (-1, 1, 1.), (0, 1, 1.)], 4.0);
+}
+
+impl<C, D> Algorithm<C, D> for AlgoSierraLite
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let (kernel, divisor) = Self::KERNEL;
+        diffuse_with_kernel(
+            image, palette, difference, options, control, kernel, divisor,
+        )
+    }
+}
+
+/// Atkinson dithering, as used by the classic Macintosh.
+///
+/// Only 6/8 of the error is diffused, which loses detail in very light and
+/// very dark areas but produces crisp, high-contrast output.
+pub struct AlgoAtkinson;
+
+impl AlgoAtkinson {
+    const KERNEL: Kernel = (
+        &[
+            (1, 0, 1.),
+            (2, 0, 1.),
+            (-1, 1, 1.),
+            (0, 1, 1.),
+            (1, 1, 1.),
+            (0, 2, 1.),
+        ],
+        8.0,
+    );
+}
+
+impl<C, D> Algorithm<C, D> for AlgoAtkinson
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Clamp,
+    C: Copy,
+    C: HueChannel,
+    C: IntoColor<Srgb>,
+    D: Difference<C>,
+    Srgb: IntoColor<C>,
+{
+    fn run(
+        image: RgbaImage,
+        palette: &Palette<C>,
+        difference: &D,
+        options: &Options,
+        control: &Control,
+    ) -> Result<RgbaImage, TimedOut> {
+        let (kernel, divisor) = Self::KERNEL;
+        diffuse_with_kernel(
+            image, palette, difference, options, control, kernel, divisor,
+        )
+    }
+}
+
+//////////////////////
+// Runtime dispatch //
+//////////////////////
+
+/// An algorithm chosen at runtime, see the `Algo*` types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlgorithmKind {
+    Threshold,
+    Random,
+    FloydSteinberg,
+    FloydSteinberg1D,
+    Stucki,
+    Jarvis,
+    Burkes,
+    Sierra,
+    SierraTwoRow,
+    SierraLite,
+    Atkinson,
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    Halftone,
+}
+
+/// A color space chosen at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    LinSrgb,
+    Cielab,
+    Cieluv,
+    Cielch,
+    Oklab,
+    Okhsl,
+    Okhsv,
+}
+
+/// A difference chosen at runtime, see the `Diff*` types.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DifferenceKind {
+    Euclid,
+    HyAb,
+    Ciede2000,
+    Cie76,
+    /// See [`DiffCie94`].
+    Cie94 {
+        kl: f32,
+        k1: f32,
+        k2: f32,
+    },
+    Oklab,
+    /// See [`DiffCiede2000Weighted`].
+    Ciede2000Weighted {
+        kl: f32,
+        kc: f32,
+        kh: f32,
+    },
+    /// See [`DiffCmc`].
+    Cmc {
+        l: f32,
+        c: f32,
+    },
+    Redmean,
+    Manhattan,
+    /// See [`DiffWeightedEuclid`].
+    WeightedEuclid([f32; 3]),
+}
+
+/// Everything [`dither`] needs to know, without any generics.
+#[derive(Debug, Clone)]
+pub struct DitherConfig {
+    pub algorithm: AlgorithmKind,
+    pub color_space: ColorSpace,
+    pub difference: DifferenceKind,
+    /// Clamp colors before computing their difference, see [`DiffClamp`].
+    pub clamp: bool,
+    pub palette: Vec<Srgb<u8>>,
+    /// See [`Options::with_seed`].
+    pub seed: u64,
+    /// See [`Options::with_noise_radius`].
+    pub noise_radius: [f32; 3],
+    /// See [`Options::with_cell_size`].
+    pub cell_size: u32,
+    /// Dither only the lightness and keep the chroma and hue of each pixel,
+    /// see [`restore_chroma`].
+    pub lightness_only: bool,
+}
+
+impl DitherConfig {
+    /// Floyd-Steinberg in oklab with euclidean differences.
+    pub fn new(palette: Vec<Srgb<u8>>) -> Self {
+        Self {
+            algorithm: AlgorithmKind::FloydSteinberg,
+            color_space: ColorSpace::Oklab,
+            difference: DifferenceKind::Euclid,
+            clamp: false,
+            palette,
+            seed: 0,
+            noise_radius: [0.0; 3],
+            cell_size: 8,
+            lightness_only: false,
+        }
+    }
+
+    /// Check the fields that would otherwise make [`dither`] panic.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.palette.is_empty() {
+            return Err(ConfigError::EmptyPalette);
+        }
+        if let DifferenceKind::WeightedEuclid(weights) = self.difference {
+            if !weights.iter().all(|w| w.is_finite() && *w >= 0.0) {
+                return Err(ConfigError::InvalidWeights);
+            }
+        }
+        // The factors are divisors in the formulas of their differences.
+        let positive = |k: f32| k > 0.0 && k.is_finite();
+        let offset = |k: f32| k >= 0.0 && k.is_finite();
+        let valid_factors = match self.difference {
+            DifferenceKind::Cie94 { kl, k1, k2 } => positive(kl) && offset(k1) && offset(k2),
+            DifferenceKind::Ciede2000Weighted { kl, kc, kh } => {
+                [kl, kc, kh].into_iter().all(positive)
+            }
+            DifferenceKind::Cmc { l, c } => positive(l) && positive(c),
+            _ => true,
+        };
+        if !valid_factors {
+            return Err(ConfigError::InvalidDifferenceFactors);
+        }
+        if !self.noise_radius.iter().all(|r| r.is_finite() && *r >= 0.0) {
+            return Err(ConfigError::InvalidNoiseRadius);
+        }
+        if !(1..=MAX_CELL_SIZE).contains(&self.cell_size) {
+            return Err(ConfigError::InvalidCellSize);
+        }
+        Ok(())
+    }
+}
+
+/// A config that [`dither`] can't run.
+#[derive(Debug)]
+pub enum ConfigError {
+    EmptyPalette,
+    InvalidWeights,
+    InvalidDifferenceFactors,
+    InvalidNoiseRadius,
+    InvalidCellSize,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyPalette => write!(f, "dither requires at least one palette color"),
+            Self::InvalidWeights => {
+                write!(f, "channel weights must be finite and must not be negative")
+            }
+            Self::InvalidDifferenceFactors => {
+                write!(
+                    f,
+                    "weighting factors of differences must be positive and finite"
+                )
+            }
+            Self::InvalidNoiseRadius => {
+                write!(f, "noise radius must be finite and must not be negative")
+            }
+            Self::InvalidCellSize => {
+                write!(f, "cell size must be between 1 and {MAX_CELL_SIZE}")
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Dither an image according to a config chosen at runtime.
+///
+/// This picks the algorithm, color space and difference types with
+/// [`dispatch`], the same way the command line tool does.
+///
+/// Fails if the config is invalid, see [`DitherConfig::validate`].
+///
+/// # Example
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use mark::dither::{self, DitherConfig};
+/// use palette::Srgb;
+///
+/// let mut image = RgbaImage::from_fn(2, 2, |x, _| Rgba([200 * x as u8, 0, 0, 255]));
+/// let palette = vec![Srgb::new(0, 0, 0), Srgb::new(255, 0, 0)];
+/// dither::dither(&mut image, DitherConfig::new(palette)).unwrap();
+///
+/// assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+/// assert_eq!(image.get_pixel(1, 0), &Rgba([255, 0, 0, 255]));
+/// ```
+pub fn dither(image: &mut RgbaImage, config: DitherConfig) -> Result<(), ConfigError> {
+    config.validate()?;
+
+    let original = config.lightness_only.then(|| image.clone());
+    if config.lightness_only {
+        to_lightness(image);
+    }
+
+    let input = std::mem::take(image);
+    let choice = Choice {
+        algorithm: config.algorithm,
+        color_space: config.color_space,
+        difference: config.difference,
+        clamp: config.clamp,
+    };
+    *image = dispatch(
+        choice,
+        DitherWithConfig {
+            image: input,
+            config: &config,
+        },
+    );
+    if let Some(original) = original {
+        restore_chroma(image, &original);
+    }
+    Ok(())
+}
+
+/// Runs [`dither`] once the types are known.
+struct DitherWithConfig<'a> {
+    image: RgbaImage,
+    config: &'a DitherConfig,
+}
+
+impl Dispatch for DitherWithConfig<'_> {
+    type Output = RgbaImage;
+
+    fn run<A, C, D>(self, difference: D) -> RgbaImage
+    where
+        A: Algorithm<C, D>,
+        C: DispatchColor,
+        D: Difference<C> + Sync,
+        Srgb: IntoColor<C>,
+    {
+        let Self { image, config } = self;
+        let options = Options::new()
+            .with_seed(config.seed)
+            .with_noise_radius(config.noise_radius)
+            .with_cell_size(config.cell_size);
+        A::run(
+            image,
+            &Palette::from_srgb(&config.palette),
+            &difference,
+            &options,
+            &Control::new(),
+        )
+        .expect("dithering without a deadline never times out")
+    }
+}
+
+/// The algorithm, color space and difference chosen at runtime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Choice {
+    pub algorithm: AlgorithmKind,
+    pub color_space: ColorSpace,
+    pub difference: DifferenceKind,
+    /// Clamp colors before computing their difference, see [`DiffClamp`].
+    pub clamp: bool,
+}
+
+/// Everything [`Dispatch::run`] may rely on about the chosen color space.
+///
+/// This is implemented for every color space of [`ColorSpace`].
+pub trait DispatchColor:
+    fmt::Debug
+    + AsMut<[f32; 3]>
+    + AsRef<[f32; 3]>
+    + Clamp
+    + Copy
+    + HueChannel
+    + IntoColor<Lab>
+    + IntoColor<Oklab>
+    + IntoColor<Srgb>
+    + Send
+    + Sync
+{
+}
+
+impl<C> DispatchColor for C where
+    C: fmt::Debug
+        + AsMut<[f32; 3]>
+        + AsRef<[f32; 3]>
+        + Clamp
+        + Copy
+        + HueChannel
+        + IntoColor<Lab>
+        + IntoColor<Oklab>
+        + IntoColor<Srgb>
+        + Send
+        + Sync
+{
+}
+
+/// Code that is generic over the algorithm, color space and difference, see
+/// [`dispatch`].
+pub trait Dispatch {
+    type Output;
+
+    fn run<A, C, D>(self, difference: D) -> Self::Output
+    where
+        A: Algorithm<C, D>,
+        C: DispatchColor,
+        D: Difference<C> + Sync,
+        Srgb: IntoColor<C>;
+}
+
+/// Run generic code with the types chosen at runtime.
+///
+/// This is how [`dither`] and the command line tool turn their config into a
+/// version of an algorithm compiled for one color space and difference.
+///
+/// # Panics
+///
+/// Panics if the weights of [`DifferenceKind::WeightedEuclid`] are negative,
+/// see [`DitherConfig::validate`].
+pub fn dispatch<T: Dispatch>(choice: Choice, handler: T) -> T::Output {
+    match choice.color_space {
+        ColorSpace::Srgb => dispatch_c::<Srgb, T>(choice, handler),
+        ColorSpace::LinSrgb => dispatch_c::<LinSrgb, T>(choice, handler),
+        ColorSpace::Cielab => dispatch_c::<Lab, T>(choice, handler),
+        ColorSpace::Cieluv => dispatch_c::<Luv, T>(choice, handler),
+        ColorSpace::Cielch => dispatch_c::<Lch, T>(choice, handler),
+        ColorSpace::Oklab => dispatch_c::<Oklab, T>(choice, handler),
+        ColorSpace::Okhsl => dispatch_c::<Okhsl, T>(choice, handler),
+        ColorSpace::Okhsv => dispatch_c::<Okhsv, T>(choice, handler),
+    }
+}
+
+fn dispatch_c<C, T>(choice: Choice, handler: T) -> T::Output
+where
+    C: DispatchColor,
+    Srgb: IntoColor<C>,
+    T: Dispatch,
+{
+    use DifferenceKind::*;
+    match (choice.difference, choice.clamp) {
+        (Euclid, false) => dispatch_cd::<C, _, T>(choice, handler, DiffEuclid),
+        (Euclid, true) => dispatch_cd::<C, _, T>(choice, handler, DiffClamp(DiffEuclid)),
+        (HyAb, false) => dispatch_cd::<C, _, T>(choice, handler, DiffHyAb),
+        (HyAb, true) => dispatch_cd::<C, _, T>(choice, handler, DiffClamp(DiffHyAb)),
+        (Ciede2000, false) => dispatch_cd::<C, _, T>(choice, handler, DiffCiede2000),
+        (Ciede2000, true) => dispatch_cd::<C, _, T>(choice, handler, DiffClamp(DiffCiede2000)),
+        (Cie76, false) => dispatch_cd::<C, _, T>(choice, handler, DiffCie76),
+        (Cie76, true) => dispatch_cd::<C, _, T>(choice, handler, DiffClamp(DiffCie76)),
+        (Oklab, false) => dispatch_cd::<C, _, T>(choice, handler, DiffOklab),
+        (Oklab, true) => dispatch_cd::<C, _, T>(choice, handler, DiffClamp(DiffOklab)),
+        (Cie94 { kl, k1, k2 }, false) => {
+            dispatch_cd::<C, _, T>(choice, handler, DiffCie94 { kl, k1, k2 })
+        }
+        (Cie94 { kl, k1, k2 }, true) => {
+            let difference = DiffClamp(DiffCie94 { kl, k1, k2 });
+            dispatch_cd::<C, _, T>(choice, handler, difference)
+        }
+        (Ciede2000Weighted { kl, kc, kh }, false) => {
+            dispatch_cd::<C, _, T>(choice, handler, DiffCiede2000Weighted { kl, kc, kh })
+        }
+        (Ciede2000Weighted { kl, kc, kh }, true) => {
+            let difference = DiffClamp(DiffCiede2000Weighted { kl, kc, kh });
+            dispatch_cd::<C, _, T>(choice, handler, difference)
+        }
+        (Cmc { l, c }, false) => dispatch_cd::<C, _, T>(choice, handler, DiffCmc { l, c }),
+        (Cmc { l, c }, true) => {
+            dispatch_cd::<C, _, T>(choice, handler, DiffClamp(DiffCmc { l, c }))
+        }
+        (Redmean, false) => dispatch_cd::<C, _, T>(choice, handler, DiffRedmean),
+        (Redmean, true) => dispatch_cd::<C, _, T>(choice, handler, DiffClamp(DiffRedmean)),
+        (Manhattan, false) => dispatch_cd::<C, _, T>(choice, handler, DiffManhattan),
+        (Manhattan, true) => dispatch_cd::<C, _, T>(choice, handler, DiffClamp(DiffManhattan)),
+        (WeightedEuclid(weights), false) => {
+            dispatch_cd::<C, _, T>(choice, handler, DiffWeightedEuclid::new(weights))
+        }
+        (WeightedEuclid(weights), true) => {
+            let difference = DiffClamp(DiffWeightedEuclid::new(weights));
+            dispatch_cd::<C, _, T>(choice, handler, difference)
+        }
+    }
+}
+
+fn dispatch_cd<C, D, T>(choice: Choice, handler: T, difference: D) -> T::Output
+where
+    C: DispatchColor,
+    D: Difference<C> + Sync,
+    Srgb: IntoColor<C>,
+    T: Dispatch,
+{
+    use AlgorithmKind::*;
+    match choice.algorithm {
+        Threshold => handler.run::<AlgoThreshold, C, D>(difference),
+        Random => handler.run::<AlgoRandom, C, D>(difference),
+        FloydSteinberg => handler.run::<AlgoFloydSteinberg, C, D>(difference),
+        FloydSteinberg1D => handler.run::<AlgoFloydSteinberg1D, C, D>(difference),
+        Stucki => handler.run::<AlgoStucki, C, D>(difference),
+        Jarvis => handler.run::<AlgoJarvis, C, D>(difference),
+        Burkes => handler.run::<AlgoBurkes, C, D>(difference),
+        Sierra => handler.run::<AlgoSierra, C, D>(difference),
+        SierraTwoRow => handler.run::<AlgoSierraTwoRow, C, D>(difference),
+        SierraLite => handler.run::<AlgoSierraLite, C, D>(difference),
+        Atkinson => handler.run::<AlgoAtkinson, C, D>(difference),
+        Bayer2 => handler.run::<AlgoBayer<2>, C, D>(difference),
+        Bayer4 => handler.run::<AlgoBayer<4>, C, D>(difference),
+        Bayer8 => handler.run::<AlgoBayer<8>, C, D>(difference),
+        Halftone => handler.run::<AlgoHalftone, C, D>(difference),
+    }
+}
+
+///////////////
+// Lightness //
+///////////////
+
+/// Replace the color of every pixel with a gray of the same oklab lightness.
+///
+/// Dithering the result against a palette decides only the lightness of each
+/// pixel, afterwards [`restore_chroma`] adds back the original colors.
+pub fn to_lightness(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        let color: Oklab = util::pixel_to_color(*pixel);
+        util::update_pixel_with_color(pixel, Oklab::new(color.l, 0.0, 0.0));
+    }
+}
+
+/// Like [`to_lightness`], but for an image with channels in the range `0..=1`
+/// like the source of [`Options::with_source`].
+pub fn to_lightness_f32(image: &mut Rgba32FImage) {
+    for Rgba([r, g, b, _]) in image.pixels_mut() {
+        let color: Oklab = Srgb::new(*r, *g, *b).into_color();
+        let gray: Srgb = Oklab::new(color.l, 0.0, 0.0).into_color();
+        (*r, *g, *b) = gray.into_components();
+    }
+}
+
+/// Combine the oklab lightness of every pixel with the oklab a and b of the
+/// same pixel in the original image, keeping the alpha channel.
+///
+/// Together with [`to_lightness`], this dithers only the lightness of an image
+/// like a halftone over the original colors. Colors outside of the srgb gamut
+/// are clamped, which reduces the chroma of saturated colors that are dithered
+/// to very dark or bright values.
+///
+/// # Example
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use mark::dither::{self, DitherConfig};
+/// use palette::{IntoColor, Oklab, Srgb};
+///
+/// let original = RgbaImage::from_fn(8, 8, |x, y| Rgba([200, 110 + 10 * x as u8, 80 + 5 * y as u8, 255]));
+/// let palette = vec![Srgb::new(140, 140, 140), Srgb::new(190, 190, 190)];
+/// let mut config = DitherConfig::new(palette);
+/// config.lightness_only = true;
+///
+/// let mut image = original.clone();
+/// dither::dither(&mut image, config).unwrap();
+///
+/// let oklab = |Rgba([r, g, b, _]): Rgba<u8>| -> Oklab { Srgb::new(r, g, b).into_format().into_color() };
+/// for (before, after) in original.pixels().zip(image.pixels()) {
+///     let (before, after) = (oklab(*before), oklab(*after));
+///     assert!((before.a - after.a).abs() < 0.005);
+///     assert!((before.b - after.b).abs() < 0.005);
+/// }
+/// ```
+pub fn restore_chroma(image: &mut RgbaImage, original: &RgbaImage) {
+    for (pixel, original) in image.pixels_mut().zip(original.pixels()) {
+        let color: Oklab = util::pixel_to_color(*pixel);
+        let original: Oklab = util::pixel_to_color(*original);
+        util::update_pixel_with_color(pixel, Oklab::new(color.l, original.a, original.b));
+    }
+}
+
+////////////////////////
+// Temporal dithering //
+////////////////////////
+
+/// Compute the image a second frame should be dithered towards so that the
+/// average of both frames approximates the original image.
+///
+/// Displays average frames in linear light, so the target is `2 * original -
+/// first` in linear srgb, clamped to the displayable range.
+pub fn temporal_target(original: &RgbaImage, first: &RgbaImage) -> RgbaImage {
+    let mut target = original.clone();
+    for (pixel, first) in target.pixels_mut().zip(first.pixels()) {
+        let original: LinSrgb = util::pixel_to_color(*pixel);
+        let first: LinSrgb = util::pixel_to_color(*first);
+        let color = (original * 2.0 - first).clamp();
+        util::update_pixel_with_color(pixel, color);
+    }
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lab(l: f32, a: f32, b: f32) -> Lab {
+        Lab::new(l, a, b)
+    }
+
+    #[track_caller]
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    /// Pairs from Sharma, Wu and Dalal, "The CIEDE2000 Color-Difference
+    /// Formula: Implementation Notes, Supplementary Test Data, and
+    /// Mathematical Observations", table 1.
+    const CIEDE2000_PAIRS: [([f32; 3], [f32; 3], f32); 12] = [
+        ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+        ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+        ([50.0, 2.8361, -74.0200], [50.0, 0.0, -82.7485], 3.4412),
+        ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+        ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+        ([50.0, 2.5, 0.0], [61.0, -5.0, 29.0], 22.8977),
+        ([50.0, 2.5, 0.0], [56.0, -27.0, -3.0], 31.9030),
+        ([50.0, 2.5, 0.0], [58.0, 24.0, 15.0], 19.4535),
+        (
+            [60.2574, -34.0099, 36.2677],
+            [60.4626, -34.1751, 39.4387],
+            1.2644,
+        ),
+        (
+            [63.0109, -31.0961, -5.8663],
+            [62.8187, -29.7946, -4.0864],
+            1.2630,
+        ),
+        (
+            [2.0776, 0.0795, -1.1350],
+            [0.9033, -0.0636, -0.5514],
+            0.9082,
+        ),
+        ([50.0, -0.0010, 2.4900], [50.0, 0.0010, -2.4900], 4.8045),
+    ];
+
+    const UNWEIGHTED: DiffCiede2000Weighted = DiffCiede2000Weighted {
+        kl: 1.0,
+        kc: 1.0,
+        kh: 1.0,
+    };
+
+    #[test]
+    fn ciede2000_weighted_matches_reference_pairs() {
+        for ([l1, a1, b1], [l2, a2, b2], expected) in CIEDE2000_PAIRS {
+            let (a, b) = (lab(l1, a1, b1), lab(l2, a2, b2));
+            assert_close(UNWEIGHTED.diff(a, b), expected, 1e-3);
+            assert_close(UNWEIGHTED.diff(b, a), expected, 1e-3);
+        }
+    }
+
+    #[test]
+    fn ciede2000_weighted_with_unit_weights_matches_ciede2000() {
+        for ([l1, a1, b1], [l2, a2, b2], _) in CIEDE2000_PAIRS {
+            let (a, b) = (lab(l1, a1, b1), lab(l2, a2, b2));
+            assert_close(UNWEIGHTED.diff(a, b), DiffCiede2000.diff(a, b), 1e-3);
+        }
+    }
+
+    #[test]
+    fn ciede2000_weights_scale_their_term() {
+        // Only the lightness differs, so doubling its weight halves the result.
+        let (a, b) = (lab(40.0, 10.0, 10.0), lab(60.0, 10.0, 10.0));
+        let weighted = DiffCiede2000Weighted {
+            kl: 2.0,
+            ..UNWEIGHTED
+        };
+        assert_close(weighted.diff(a, b), UNWEIGHTED.diff(a, b) / 2.0, 1e-4);
+    }
+
+    #[test]
+    fn random_mixes_hues_across_zero() {
+        let lch = |hue: f32| Lch::new(50.0, 40.0, hue);
+        let srgb: Srgb<u8> = IntoColor::<Srgb>::into_color(lch(0.0)).into_format();
+        let image = RgbaImage::from_pixel(32, 32, Rgba([srgb.red, srgb.green, srgb.blue, 255]));
+        let palette = Palette::new(vec![lch(350.0), lch(10.0)]);
+        let options = Options::new();
+        let result = AlgoRandom::run(image, &palette, &DiffEuclid, &options, &Control::new());
+
+        let first: Srgb<u8> = IntoColor::<Srgb>::into_color(lch(350.0)).into_format();
+        let first = Rgba([first.red, first.green, first.blue, 255]);
+        let count = result.unwrap().pixels().filter(|p| **p == first).count();
+        // Both hues are equally near, so each should be chosen about half the time.
+        assert!((400..=624).contains(&count), "chose hue 350 {count} times");
+    }
+
+    /// A gradient over all hues and lightnesses.
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(32, 16, |x, y| {
+            Rgba([(x * 8) as u8, (y * 16) as u8, (255 - x * 8) as u8, 255])
+        })
+    }
+
+    fn gray_palette() -> Palette<Oklab> {
+        Palette::from_srgb(&[
+            Srgb::new(0, 0, 0),
+            Srgb::new(128, 128, 128),
+            Srgb::new(255, 255, 255),
+        ])
+    }
+
+    #[track_caller]
+    fn assert_only_colors(image: &RgbaImage, colors: &[Srgb<u8>]) {
+        for Rgba([r, g, b, _]) in image.pixels() {
+            let color = Srgb::new(*r, *g, *b);
+            assert!(colors.contains(&color), "{color:?} is not in {colors:?}");
+        }
+    }
+
+    const PRIMARIES: [Srgb<u8>; 4] = [
+        Srgb::new(0, 0, 0),
+        Srgb::new(255, 0, 0),
+        Srgb::new(0, 128, 255),
+        Srgb::new(255, 255, 255),
+    ];
+
+    #[test]
+    fn burkes_only_uses_palette_colors() {
+        let palette = Palette::<Oklab>::from_srgb(&PRIMARIES);
+        let result = AlgoBurkes::run(
+            gradient(),
+            &palette,
+            &DiffEuclid,
+            &Options::new(),
+            &Control::new(),
+        );
+        assert_only_colors(&result.unwrap(), &PRIMARIES);
+    }
+
+    #[test]
+    fn nearest_only_uses_palette_colors() {
+        let palette = Palette::<Lab>::from_srgb(&PRIMARIES);
+        let difference = DiffCiede2000;
+        let result = AlgoNearest::run(
+            gradient(),
+            &palette,
+            &difference,
+            &Options::new(),
+            &Control::new(),
+        );
+        let result = result.unwrap();
+        assert_only_colors(&result, &PRIMARIES);
+        // Every pixel is remapped to its own nearest color.
+        for (pixel, original) in result.pixels().zip(gradient().pixels()) {
+            let [r, g, b, _] = original.0;
+            let color: Lab = Srgb::new(r, g, b).into_format().into_color();
+            let nearest: Srgb = palette.nearest(&difference, color).into_color();
+            let [nr, ng, nb] = nearest.into_format::<u8>().into();
+            assert_eq!(pixel.0, [nr, ng, nb, 255]);
+        }
+    }
+
+    #[test]
+    fn floyd_steinberg_without_strength_is_threshold() {
+        let (palette, control) = (gray_palette(), Control::new());
+        let options = Options::new().with_diffusion_strength(0.0);
+        let diffused =
+            AlgoFloydSteinberg::run(gradient(), &palette, &DiffEuclid, &options, &control);
+        let threshold = AlgoThreshold::run(gradient(), &palette, &DiffEuclid, &options, &control);
+        assert_eq!(diffused.unwrap(), threshold.unwrap());
+    }
+
+    #[test]
+    fn kernels_diffuse_the_entire_error() {
+        let kernels = [
+            ("floyd-steinberg", AlgoFloydSteinberg::KERNEL),
+            ("floyd-steinberg-1d", AlgoFloydSteinberg1D::KERNEL),
+            ("stucki", AlgoStucki::KERNEL),
+            ("jarvis", AlgoJarvis::KERNEL),
+            ("burkes", AlgoBurkes::KERNEL),
+            ("sierra", AlgoSierra::KERNEL),
+            ("sierra-two-row", AlgoSierraTwoRow::KERNEL),
+            ("sierra-lite", AlgoSierraLite::KERNEL),
+        ];
+        for (name, (kernel, divisor)) in kernels {
+            let sum = kernel.iter().map(|(_, _, n)| n).sum::<f32>();
+            assert_eq!(sum, divisor, "{name}");
+        }
+        assert_eq!(AlgoJarvis::KERNEL.1, 48.0);
+
+        // Atkinson deliberately diffuses only 6/8 of the error.
+        let (kernel, divisor) = AlgoAtkinson::KERNEL;
+        let sum = kernel.iter().map(|(_, _, n)| n).sum::<f32>();
+        assert_eq!(sum / divisor, 0.75);
+    }
+
+    #[test]
+    fn atkinson_matches_reference_buffer() {
+        let (width, height) = (12, 8);
+        let gray = |x: u32, y: u32| ((x * 23 + y * 41) % 256) as u8;
+        let image = RgbaImage::from_fn(width, height, |x, y| {
+            let v = gray(x, y);
+            Rgba([v, v, v, 255])
+        });
+        let palette = Palette::<Srgb>::from_srgb(&[Srgb::new(0, 0, 0), Srgb::new(255, 255, 255)]);
+        let result = AlgoAtkinson::run(
+            image,
+            &palette,
+            &DiffEuclid,
+            &Options::new(),
+            &Control::new(),
+        );
+
+        // A straightforward single channel Atkinson on a buffer of floats.
+        let mut buffer = (0..height)
+            .flat_map(|y| (0..width).map(move |x| gray(x, y) as f32 / 255.0))
+            .collect::<Vec<_>>();
+        let mut expected = RgbaImage::new(width, height);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let before = buffer[(y * width as i32 + x) as usize];
+                let after = if before <= 0.5 { 0.0 } else { 1.0 };
+                let v = (after * 255.0) as u8;
+                expected.put_pixel(x as u32, y as u32, Rgba([v, v, v, 255]));
+                let error = (before - after) * (1.0 / 8.0);
+                for (dx, dy) in [(1, 0), (2, 0), (-1, 1), (0, 1), (1, 1), (0, 2)] {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if (0..width as i32).contains(&nx) && ny < height as i32 {
+                        buffer[(ny * width as i32 + nx) as usize] += error;
+                    }
+                }
+            }
+        }
+
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    /// A 16x16 checkerboard of two colors.
+    fn checkerboard(a: Rgba<u8>, b: Rgba<u8>) -> RgbaImage {
+        RgbaImage::from_fn(16, 16, |x, y| if (x + y) % 2 == 0 { a } else { b })
+    }
+
+    #[test]
+    fn error_clamp_keeps_checkerboards_intact() {
+        let (black, white) = (Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255]));
+        let (dark, light) = (Rgba([64, 64, 64, 255]), Rgba([192, 192, 192, 255]));
+        let palette =
+            Palette::<Oklab>::from_srgb(&[Srgb::new(64, 64, 64), Srgb::new(192, 192, 192)]);
+        let run = |clamp| {
+            let options = Options::new().with_error_clamp(clamp);
+            let image = checkerboard(black, white);
+            AlgoFloydSteinberg::run(image, &palette, &DiffEuclid, &options, &Control::new())
+                .unwrap()
+        };
+
+        // Neither palette color reaches black or white, so the error keeps
+        // growing beyond the range of oklab and eventually flips pixels.
+        let expected = checkerboard(dark, light);
+        assert_ne!(run(false), expected);
+        assert_eq!(run(true), expected);
+    }
+
+    #[test]
+    fn seeds_shift_ordered_patterns() {
+        let image = RgbaImage::from_pixel(16, 16, Rgba([100, 100, 100, 255]));
+        let palette = Palette::new(vec![Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)]);
+        let run = |seed| {
+            let options = Options::new().with_seed(seed);
+            AlgoBayer::<4>::run(
+                image.clone(),
+                &palette,
+                &DiffEuclid,
+                &options,
+                &Control::new(),
+            )
+            .unwrap()
+        };
+
+        let (a, b) = (run(1), run(2));
+        assert_eq!(a, run(1));
+        assert_ne!(a, b);
+        let shifted =
+            |dx, dy| RgbaImage::from_fn(16, 16, |x, y| *a.get_pixel((x + dx) % 16, (y + dy) % 16));
+        let shifts = (0..4).flat_map(|dy| (0..4).map(move |dx| (dx, dy)));
+        assert!(shifts.into_iter().any(|(dx, dy)| shifted(dx, dy) == b));
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_lookups_match_scalar_ones() {
+        fn scalar<C: AsRef<[f32; 3]> + Copy + HueChannel + Sync>(
+            colors: &[C],
+            to: C,
+            farthest: bool,
+        ) -> usize {
+            let differences = colors.iter().map(|c| DiffEuclid.diff(*c, to)).enumerate();
+            let cmp = |(_, a): &(usize, f32), (_, b): &(usize, f32)| a.total_cmp(b);
+            let nearest = if farthest {
+                differences.max_by(cmp)
+            } else {
+                differences.min_by(cmp)
+            };
+            nearest.unwrap().0
+        }
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut oklab = || {
+            let mut channel = |range| rng.gen_range(range);
+            Oklab::new(channel(0.0..1.0), channel(-0.4..0.4), channel(-0.4..0.4))
+        };
+        for len in [1, 2, 3, 4, 5, 7, 8, 9, 256] {
+            let mut colors = (0..len).map(|_| oklab()).collect::<Vec<_>>();
+            // Repeated colors check the tie-breaking.
+            colors.extend(colors.clone().into_iter().take(len / 2));
+            let lch = colors
+                .iter()
+                .map(|c| (*c).into_color())
+                .collect::<Vec<Lch>>();
+            for _ in 0..64 {
+                let to = oklab();
+                for farthest in [false, true] {
+                    let simd = simd::nearest_euclid(&colors, to, farthest);
+                    assert_eq!(simd, Some(scalar(&colors, to, farthest)));
+
+                    // Wrapping the hue rounds differently.
+                    let to: Lch = to.into_color();
+                    let simd = simd::nearest_euclid(&lch, to, farthest).unwrap();
+                    let expected = scalar(&lch, to, farthest);
+                    let [a, b] = [simd, expected].map(|i| DiffEuclid.diff(lch[i], to));
+                    assert_close(a, b, 1e-3);
+                }
+            }
+        }
+        assert_eq!(simd::nearest_euclid::<Oklab>(&[], oklab(), false), None);
+    }
+
+    #[test]
+    fn channel_deltas_wrap_hues() {
+        let lch = |hue: f32| Lch::new(50.0, 40.0, hue);
+        assert_eq!(channel_deltas(lch(350.0), lch(10.0)), [0.0, 0.0, -20.0]);
+        assert_eq!(channel_deltas(lch(10.0), lch(350.0)), [0.0, 0.0, 20.0]);
+        assert_eq!(channel_deltas(lch(90.0), lch(60.0)), [0.0, 0.0, 30.0]);
+        assert_eq!(channel_deltas(lch(-90.0), lch(180.0)), [0.0, 0.0, 90.0]);
+        // Other channels and other color spaces don't wrap.
+        let [dl, dc, _] = channel_deltas(Lch::new(90.0, 400.0, 0.0), Lch::new(-300.0, 0.0, 0.0));
+        assert_eq!([dl, dc], [390.0, 400.0]);
+        let deltas = channel_deltas(lab(50.0, 350.0, 10.0), lab(50.0, 10.0, 350.0));
+        assert_eq!(deltas, [0.0, 340.0, -340.0]);
+    }
+
+    #[test]
+    fn hue_sweep_across_zero_avoids_the_opposite_hue() {
+        let lch = |hue: f32| Lch::new(60.0, 30.0, hue);
+        let to_pixel = |color: Lch| {
+            let srgb: Srgb<u8> = IntoColor::<Srgb>::into_color(color).into_format();
+            Rgba([srgb.red, srgb.green, srgb.blue, 255])
+        };
+        // Hues from 350 over 0 to 10 degrees.
+        let image = RgbaImage::from_fn(21, 8, |x, _| to_pixel(lch(350.0 + x as f32)));
+        let palette = Palette::new(vec![lch(0.0), lch(90.0), lch(180.0), lch(270.0)]);
+        let result = AlgoFloydSteinberg::run(
+            image,
+            &palette,
+            &DiffEuclid,
+            &Options::new(),
+            &Control::new(),
+        )
+        .unwrap();
+
+        let opposite = to_pixel(lch(180.0));
+        assert!(result.pixels().all(|p| *p != opposite));
+    }
+
+    #[test]
+    fn merge_similar_averages_hues_across_zero() {
+        let palette = Palette::new(vec![
+            Lch::new(50.0, 40.0, 350.0),
+            Lch::new(50.0, 40.0, 10.0),
+        ]);
+        let merged = palette.merge_similar(20.0);
+        let [color] = merged.colors() else {
+            panic!("expected a single color, got {:?}", merged.colors());
+        };
+        let hue = color.hue.into_positive_degrees();
+        assert!(!(0.01..=359.99).contains(&hue), "merged hue is {hue}");
+    }
+
+    #[test]
+    fn redmean_and_euclid_disagree_on_reddish_colors() {
+        let target = Srgb::new(0.9, 0.1, 0.1);
+        let less_red = Srgb::new(0.6, 0.1, 0.1);
+        let more_blue = Srgb::new(0.9, 0.1, 0.43);
+        let palette = Palette::new(vec![less_red, more_blue]);
+
+        // Redmean weights red more and blue less when both colors are reddish.
+        assert_eq!(palette.nearest(&DiffEuclid, target), less_red);
+        assert_eq!(palette.nearest(&DiffRedmean, target), more_blue);
+    }
+
+    /// The difference between two colors that may lie outside the range
+    /// converting into cielab clamps to.
+    fn unclamped<D: Difference<Lab, Prepared = Lab>>(difference: D, a: Lab, b: Lab) -> f32 {
+        difference.diff_prepared(a, b)
+    }
+
+    /// A pair from the documentation of the colour-science library, followed
+    /// by pairs that also differ in lightness.
+    const CIE94_PAIRS: [([f32; 3], [f32; 3], f32, f32); 3] = [
+        (
+            [100.0, 21.572_104, 272.228_19],
+            [100.0, 426.679_45, 72.395_91],
+            83.779_23,
+            88.335_55,
+        ),
+        ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 34.689_16, 28.250_26),
+        (
+            [60.2574, -34.0099, 36.2677],
+            [60.4626, -34.1751, 39.4387],
+            1.390_99,
+            1.389_73,
+        ),
+    ];
+
+    #[test]
+    fn cie94_matches_reference_pairs() {
+        for ([l1, a1, b1], [l2, a2, b2], graphic_arts, textiles) in CIE94_PAIRS {
+            let (a, b) = (lab(l1, a1, b1), lab(l2, a2, b2));
+            let tolerance = 1e-4 * graphic_arts;
+            assert_close(
+                unclamped(DiffCie94::GRAPHIC_ARTS, a, b),
+                graphic_arts,
+                tolerance,
+            );
+            assert_close(unclamped(DiffCie94::TEXTILES, a, b), textiles, tolerance);
+        }
+    }
+
+    /// A pair from the documentation of the colour-science library, followed
+    /// by a pair that also differs in lightness.
+    const CMC_PAIRS: [([f32; 3], [f32; 3], f32, f32); 2] = [
+        (
+            [100.0, 21.572_104, 272.228_19],
+            [100.0, 426.679_45, 72.395_91],
+            172.704_77,
+            172.704_77,
+        ),
+        ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 37.923_28, 42.108_75),
+    ];
+
+    #[test]
+    fn cmc_matches_reference_pairs() {
+        for ([l1, a1, b1], [l2, a2, b2], acceptability, perceptibility) in CMC_PAIRS {
+            let (a, b) = (lab(l1, a1, b1), lab(l2, a2, b2));
+            let tolerance = 1e-4 * perceptibility;
+            let acceptable = unclamped(DiffCmc::ACCEPTABILITY, a, b);
+            let perceptible = unclamped(DiffCmc::PERCEPTIBILITY, a, b);
+            assert_close(acceptable, acceptability, tolerance);
+            assert_close(perceptible, perceptibility, tolerance);
+        }
+    }
+
+    /// Reports the color type chosen by [`dispatch`] and whether colors
+    /// survive the round trip through it.
+    struct RoundTrip;
+
+    impl Dispatch for RoundTrip {
+        type Output = (&'static str, bool);
+
+        fn run<A, C, D>(self, _difference: D) -> Self::Output
+        where
+            A: Algorithm<C, D>,
+            C: DispatchColor,
+            D: Difference<C> + Sync,
+            Srgb: IntoColor<C>,
+        {
+            let survives = (0..=255).step_by(15).all(|v| {
+                let srgb = Srgb::new(v, 255 - v, v / 3);
+                let color: C = srgb.into_format().into_color();
+                let back: Srgb = color.into_color();
+                back.into_format::<u8>() == srgb
+            });
+            (std::any::type_name::<C>(), survives)
+        }
+    }
+
+    #[test]
+    fn color_spaces_dispatch_to_their_types_and_round_trip() {
+        let spaces = [
+            (ColorSpace::Srgb, std::any::type_name::<Srgb>()),
+            (ColorSpace::LinSrgb, std::any::type_name::<LinSrgb>()),
+            (ColorSpace::Cielab, std::any::type_name::<Lab>()),
+            (ColorSpace::Cieluv, std::any::type_name::<Luv>()),
+            (ColorSpace::Cielch, std::any::type_name::<Lch>()),
+            (ColorSpace::Oklab, std::any::type_name::<Oklab>()),
+            (ColorSpace::Okhsl, std::any::type_name::<Okhsl>()),
+            (ColorSpace::Okhsv, std::any::type_name::<Okhsv>()),
+        ];
+        for (color_space, expected) in spaces {
+            let choice = Choice {
+                algorithm: AlgorithmKind::Threshold,
+                color_space,
+                difference: DifferenceKind::Euclid,
+                clamp: false,
+            };
+            let (name, survives) = dispatch(choice, RoundTrip);
+            assert_eq!(name, expected);
+            assert!(survives, "colors change in {name}");
+        }
+    }
+
+    #[test]
+    fn dither_rejects_invalid_configs() {
+        let palette = vec![Srgb::new(0, 0, 0), Srgb::new(255, 255, 255)];
+        let invalid = [
+            DitherConfig::new(Vec::new()),
+            DitherConfig {
+                difference: DifferenceKind::WeightedEuclid([1.0, -1.0, 1.0]),
+                ..DitherConfig::new(palette.clone())
+            },
+            DitherConfig {
+                difference: DifferenceKind::WeightedEuclid([f32::INFINITY, 1.0, 1.0]),
+                ..DitherConfig::new(palette.clone())
+            },
+            DitherConfig {
+                difference: DifferenceKind::Cie94 {
+                    kl: 0.0,
+                    k1: 0.045,
+                    k2: 0.015,
+                },
+                ..DitherConfig::new(palette.clone())
+            },
+            DitherConfig {
+                difference: DifferenceKind::Cie94 {
+                    kl: 1.0,
+                    k1: f32::NAN,
+                    k2: 0.015,
+                },
+                ..DitherConfig::new(palette.clone())
+            },
+            DitherConfig {
+                difference: DifferenceKind::Ciede2000Weighted {
+                    kl: 2.0,
+                    kc: 0.0,
+                    kh: 1.0,
+                },
+                ..DitherConfig::new(palette.clone())
+            },
+            DitherConfig {
+                difference: DifferenceKind::Ciede2000Weighted {
+                    kl: f32::INFINITY,
+                    kc: 1.0,
+                    kh: 1.0,
+                },
+                ..DitherConfig::new(palette.clone())
+            },
+            DitherConfig {
+                difference: DifferenceKind::Cmc { l: 2.0, c: 0.0 },
+                ..DitherConfig::new(palette.clone())
+            },
+            DitherConfig {
+                difference: DifferenceKind::Cmc {
+                    l: f32::NAN,
+                    c: 1.0,
+                },
+                ..DitherConfig::new(palette.clone())
+            },
+            DitherConfig {
+                noise_radius: [0.0, f32::INFINITY, 0.0],
+                ..DitherConfig::new(palette.clone())
+            },
+            DitherConfig {
+                cell_size: MAX_CELL_SIZE + 1,
+                ..DitherConfig::new(palette.clone())
+            },
+        ];
+        for config in invalid {
+            let mut image = RgbaImage::new(4, 4);
+            assert!(dither(&mut image, config).is_err());
+        }
+        assert!(dither(&mut RgbaImage::new(4, 4), DitherConfig::new(palette)).is_ok());
     }
 }
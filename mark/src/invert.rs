@@ -0,0 +1,56 @@
+//! Inverting the colors of an image.
+
+use image::RgbaImage;
+use palette::{LinSrgb, Srgb};
+
+use crate::util;
+
+/// Invert the colors of an image, leaving the alpha channel untouched.
+///
+/// If `linear` is set, the linear srgb channels are inverted instead of the
+/// gamma-encoded ones. This keeps the amount of light of the negative correct,
+/// but maps midtones to much darker or brighter values.
+pub fn invert(image: &mut RgbaImage, linear: bool) {
+    for pixel in image.pixels_mut() {
+        if linear {
+            let color: LinSrgb = util::pixel_to_srgb(*pixel).into_linear();
+            let color = LinSrgb::new(1.0 - color.red, 1.0 - color.green, 1.0 - color.blue);
+            util::update_pixel_with_srgb(pixel, Srgb::from_linear(color));
+        } else {
+            let [r, g, b, _] = &mut pixel.0;
+            for channel in [r, g, b] {
+                *channel = 255 - *channel;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    /// Invert a single pixel.
+    fn inverted(pixel: [u8; 4], linear: bool) -> [u8; 4] {
+        let mut image = RgbaImage::from_pixel(1, 1, Rgba(pixel));
+        invert(&mut image, linear);
+        image.get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn invert_gamma_encoded_channels() {
+        assert_eq!(inverted([0, 0, 0, 255], false), [255, 255, 255, 255]);
+        assert_eq!(inverted([255, 0, 0, 200], false), [0, 255, 255, 200]);
+        assert_eq!(inverted([10, 100, 200, 0], false), [245, 155, 55, 0]);
+        assert_eq!(inverted([128, 128, 128, 255], false), [127, 127, 127, 255]);
+    }
+
+    #[test]
+    fn invert_linear_channels() {
+        assert_eq!(inverted([0, 0, 0, 255], true), [255, 255, 255, 255]);
+        assert_eq!(inverted([255, 0, 0, 200], true), [0, 255, 255, 200]);
+        // Midtones move far, since srgb 128 has only about 22% of the light.
+        assert_eq!(inverted([128, 64, 188, 255], true), [229, 249, 187, 255]);
+    }
+}
@@ -1,3 +1,15 @@
+pub mod adjust;
+pub mod ase;
 pub mod bw;
+pub mod compare;
 pub mod dither;
+pub mod invert;
+pub mod palette;
+pub mod posterize;
+pub mod resize;
+pub mod thermal;
 mod util;
+
+// Only used by the benchmarks.
+#[cfg(test)]
+use criterion as _;
@@ -0,0 +1,417 @@
+//! Automatic palette generation.
+
+use std::{collections::HashMap, ops::RangeInclusive};
+
+use image::RgbaImage;
+use palette::{Clamp, IntoColor, Srgb};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+/// How a group of colors is represented by a single palette color.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Representative {
+    /// The average of all colors. Smooths out the palette.
+    #[default]
+    Mean,
+    /// The per-channel median of all colors.
+    Median,
+    /// The most frequent color. Preserves actual image colors.
+    Mode,
+}
+
+/// The colors of all pixels that aren't fully transparent.
+///
+/// Fully transparent pixels usually have arbitrary colors that nobody sees, so
+/// palettes shouldn't spend colors on them.
+fn visible_colors(image: &RgbaImage) -> impl Iterator<Item = [u8; 3]> + '_ {
+    image
+        .pixels()
+        .filter(|p| p.0[3] > 0)
+        .map(|p| [p.0[0], p.0[1], p.0[2]])
+}
+
+/// A box of colors in the rgb cube.
+struct ColorBox {
+    colors: Vec<[u8; 3]>,
+}
+
+impl ColorBox {
+    /// Returns the channel with the largest range and the size of that range.
+    fn longest_axis(&self) -> (usize, u8) {
+        (0..3)
+            .map(|i| {
+                let min = self.colors.iter().map(|c| c[i]).min().unwrap_or(0);
+                let max = self.colors.iter().map(|c| c[i]).max().unwrap_or(0);
+                (i, max - min)
+            })
+            .max_by_key(|(_, range)| *range)
+            .expect("there are three channels")
+    }
+
+    fn split(mut self) -> (Self, Self) {
+        let (axis, _) = self.longest_axis();
+        self.colors.sort_unstable_by_key(|c| c[axis]);
+        let upper = self.colors.split_off(self.colors.len() / 2);
+        (self, Self { colors: upper })
+    }
+
+    fn representative(&self, representative: Representative) -> Srgb<u8> {
+        match representative {
+            Representative::Mean => self.mean(),
+            Representative::Median => self.median(),
+            Representative::Mode => self.mode(),
+        }
+    }
+
+    fn mean(&self) -> Srgb<u8> {
+        let mut sum = [0_u64; 3];
+        for color in &self.colors {
+            for (s, c) in sum.iter_mut().zip(color) {
+                *s += *c as u64;
+            }
+        }
+        let len = self.colors.len() as u64;
+        let [r, g, b] = sum.map(|s| ((s + len / 2) / len) as u8);
+        Srgb::new(r, g, b)
+    }
+
+    fn median(&self) -> Srgb<u8> {
+        let [r, g, b] = [0, 1, 2].map(|i| {
+            let mut channel = self.colors.iter().map(|c| c[i]).collect::<Vec<_>>();
+            channel.sort_unstable();
+            channel[channel.len() / 2]
+        });
+        Srgb::new(r, g, b)
+    }
+
+    fn mode(&self) -> Srgb<u8> {
+        let mut counts = HashMap::<[u8; 3], usize>::new();
+        for color in &self.colors {
+            *counts.entry(*color).or_default() += 1;
+        }
+        // Ties are broken by the color itself to keep the result deterministic.
+        let ([r, g, b], _) = counts
+            .into_iter()
+            .max_by_key(|(color, count)| (*count, *color))
+            .expect("color box is never empty");
+        Srgb::new(r, g, b)
+    }
+}
+
+/// Find up to `n` representative colors of an image using median-cut
+/// quantization.
+///
+/// Starting with a box containing every pixel, the box with the largest range
+/// along any channel is repeatedly split in half at the median of that channel.
+/// Each resulting box is represented by the mean of its colors.
+///
+/// Fewer than `n` colors are returned if the image contains fewer than `n`
+/// distinct colors. Fully transparent pixels are ignored.
+pub fn median_cut(image: &RgbaImage, n: usize) -> Vec<Srgb<u8>> {
+    median_cut_with_representative(image, n, Representative::Mean)
+}
+
+/// Like [`median_cut`], but with a configurable representative for each box.
+pub fn median_cut_with_representative(
+    image: &RgbaImage,
+    n: usize,
+    representative: Representative,
+) -> Vec<Srgb<u8>> {
+    let colors = visible_colors(image).collect::<Vec<_>>();
+    if colors.is_empty() || n == 0 {
+        return vec![];
+    }
+
+    let mut boxes = vec![ColorBox { colors }];
+    while boxes.len() < n {
+        let Some((i, _)) = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (i, b.longest_axis().1))
+            .filter(|(_, range)| *range > 0)
+            .max_by_key(|(_, range)| *range)
+        else {
+            break;
+        };
+        let (a, b) = boxes.swap_remove(i).split();
+        boxes.push(a);
+        boxes.push(b);
+    }
+
+    boxes
+        .iter()
+        .map(|b| b.representative(representative))
+        .collect()
+}
+
+/// At most this many distinct colors are clustered by [`kmeans`], the rest are
+/// skipped for speed.
+const KMEANS_MAX_SAMPLES: usize = 1 << 16;
+
+fn squared_distance<C: AsRef<[f32; 3]>>(a: &C, b: &C) -> f32 {
+    let [a1, a2, a3] = a.as_ref();
+    let [b1, b2, b3] = b.as_ref();
+    (a1 - b1).powi(2) + (a2 - b2).powi(2) + (a3 - b3).powi(2)
+}
+
+/// Find up to `n` representative colors of an image using k-means clustering
+/// in the color space `C`.
+///
+/// The centroids are initialized using k-means++ with a random number
+/// generator seeded by `seed`, then refined using Lloyd's algorithm until they
+/// no longer change or `max_iterations` is reached.
+///
+/// If the image contains at most `n` distinct colors, exactly those colors are
+/// returned. Fully transparent pixels are ignored.
+pub fn kmeans<C>(image: &RgbaImage, n: usize, max_iterations: usize, seed: u64) -> Vec<Srgb<u8>>
+where
+    C: AsMut<[f32; 3]>,
+    C: AsRef<[f32; 3]>,
+    C: Copy,
+    C: IntoColor<Srgb>,
+    Srgb: IntoColor<C>,
+{
+    let mut counts = HashMap::<[u8; 3], usize>::new();
+    for color in visible_colors(image) {
+        *counts.entry(color).or_default() += 1;
+    }
+    let mut distinct = counts.into_iter().collect::<Vec<_>>();
+    distinct.sort_unstable();
+
+    if distinct.len() <= n {
+        return distinct
+            .into_iter()
+            .map(|([r, g, b], _)| Srgb::new(r, g, b))
+            .collect();
+    }
+
+    let step = distinct.len().div_ceil(KMEANS_MAX_SAMPLES);
+    let points = distinct
+        .into_iter()
+        .step_by(step)
+        .map(|([r, g, b], count)| {
+            let color: C = Srgb::new(r, g, b).into_format().into_color();
+            (color, count as f32)
+        })
+        .collect::<Vec<_>>();
+
+    // k-means++: Each further centroid is chosen with a probability
+    // proportional to its squared distance from the nearest existing centroid.
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut centroids = vec![points[rng.gen_range(0..points.len())].0];
+    while centroids.len() < n {
+        let weights = points
+            .iter()
+            .map(|(p, w)| {
+                let nearest = centroids
+                    .iter()
+                    .map(|c| squared_distance(p, c))
+                    .fold(f32::INFINITY, f32::min);
+                nearest * w
+            })
+            .collect::<Vec<_>>();
+        let total = weights.iter().sum::<f32>();
+        if total <= 0.0 {
+            break;
+        }
+        let mut target = rng.gen_range(0.0..total);
+        let chosen = weights
+            .iter()
+            .position(|w| {
+                target -= w;
+                target < 0.0
+            })
+            .unwrap_or(points.len() - 1);
+        centroids.push(points[chosen].0);
+    }
+
+    let mut assignments = vec![usize::MAX; points.len()];
+    for _ in 0..max_iterations {
+        let mut changed = false;
+        for ((p, _), assignment) in points.iter().zip(&mut assignments) {
+            let nearest = (0..centroids.len())
+                .min_by(|a, b| {
+                    let a = squared_distance(p, &centroids[*a]);
+                    let b = squared_distance(p, &centroids[*b]);
+                    a.total_cmp(&b)
+                })
+                .expect("there is at least one centroid");
+            changed |= *assignment != nearest;
+            *assignment = nearest;
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![([0.0; 3], 0.0); centroids.len()];
+        for ((p, w), assignment) in points.iter().zip(&assignments) {
+            let (sum, total) = &mut sums[*assignment];
+            for (s, c) in sum.iter_mut().zip(p.as_ref()) {
+                *s += c * w;
+            }
+            *total += w;
+        }
+        for (centroid, (sum, total)) in centroids.iter_mut().zip(sums) {
+            // Empty clusters keep their previous centroid.
+            if total > 0.0 {
+                *centroid.as_mut() = sum.map(|s| s / total);
+            }
+        }
+    }
+
+    centroids
+        .into_iter()
+        .map(|c| {
+            let srgb: Srgb = c.into_color();
+            srgb.clamp().into_format()
+        })
+        .collect()
+}
+
+/// Bounds of [`suggest_palette_size`].
+const SUGGESTED_SIZES: RangeInclusive<usize> = 2..=256;
+
+/// Suggest how many colors a palette for an image should have.
+///
+/// The colors of the image are counted in a coarse histogram with 4 bits per
+/// channel, and the nonempty bins are sorted by count in descending order.
+/// After scaling both the bin index and the count to the range `0..=1`, the
+/// elbow of this curve is the bin farthest below the straight line from the
+/// first to the last bin. The elbow and all bins before it are considered
+/// significant, and their number is clamped to `2..=256`. Fully transparent
+/// pixels are ignored.
+pub fn suggest_palette_size(image: &RgbaImage) -> usize {
+    let mut bins = HashMap::<[u8; 3], usize>::new();
+    for [r, g, b] in visible_colors(image) {
+        *bins.entry([r >> 4, g >> 4, b >> 4]).or_default() += 1;
+    }
+    let mut counts = bins.into_values().collect::<Vec<_>>();
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+
+    let (Some(&max), Some(&min)) = (counts.first(), counts.last()) else {
+        return *SUGGESTED_SIZES.start();
+    };
+    let last = (counts.len() - 1).max(1) as f32;
+    let range = (max - min).max(1) as f32;
+    let elbow = counts
+        .iter()
+        .enumerate()
+        .map(|(i, &count)| {
+            let x = i as f32 / last;
+            let y = (count - min) as f32 / range;
+            (i, 1.0 - x - y)
+        })
+        .fold((0, f32::NEG_INFINITY), |best, (i, distance)| {
+            if distance > best.1 {
+                (i, distance)
+            } else {
+                best
+            }
+        })
+        .0;
+
+    (elbow + 1).clamp(*SUGGESTED_SIZES.start(), *SUGGESTED_SIZES.end())
+}
+
+/// Classic fixed palettes.
+pub mod builtin {
+    use palette::Srgb;
+
+    const fn hex(rgb: u32) -> Srgb<u8> {
+        Srgb::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    }
+
+    /// Every combination of the given channel values, with red changing
+    /// slowest and blue fastest.
+    const fn cube<const N: usize, const M: usize>(values: [u8; M]) -> [Srgb<u8>; N] {
+        let mut colors = [Srgb::new(0, 0, 0); N];
+        let mut i = 0;
+        while i < N {
+            let r = values[i / (M * M)];
+            let g = values[i / M % M];
+            let b = values[i % M];
+            colors[i] = Srgb::new(r, g, b);
+            i += 1;
+        }
+        colors
+    }
+
+    pub const BLACK_WHITE: [Srgb<u8>; 2] = [hex(0x000000), hex(0xffffff)];
+
+    pub const GRAYSCALE_4: [Srgb<u8>; 4] =
+        [hex(0x000000), hex(0x555555), hex(0xaaaaaa), hex(0xffffff)];
+
+    /// The four shades of green of the original Game Boy.
+    pub const GAMEBOY: [Srgb<u8>; 4] = [hex(0x0f380f), hex(0x306230), hex(0x8bac0f), hex(0x9bbc0f)];
+
+    /// The 16 colors of CGA text mode.
+    pub const CGA: [Srgb<u8>; 16] = [
+        hex(0x000000),
+        hex(0x0000aa),
+        hex(0x00aa00),
+        hex(0x00aaaa),
+        hex(0xaa0000),
+        hex(0xaa00aa),
+        hex(0xaa5500),
+        hex(0xaaaaaa),
+        hex(0x555555),
+        hex(0x5555ff),
+        hex(0x55ff55),
+        hex(0x55ffff),
+        hex(0xff5555),
+        hex(0xff55ff),
+        hex(0xffff55),
+        hex(0xffffff),
+    ];
+
+    /// The 64 colors EGA can display, two bits per channel.
+    pub const EGA: [Srgb<u8>; 64] = cube([0x00, 0x55, 0xaa, 0xff]);
+
+    /// The 216 web-safe colors.
+    pub const WEB_SAFE: [Srgb<u8>; 216] = cube([0x00, 0x33, 0x66, 0x99, 0xcc, 0xff]);
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn median_cut_separates_two_tones() {
+        // Two noisy tones, a dark blue and a light orange.
+        let image = RgbaImage::from_fn(16, 16, |x, y| {
+            let noise = ((x * 7 + y * 13) % 9) as u8;
+            if (x + y) % 2 == 0 {
+                Rgba([20 + noise, 30 + noise, 120 + noise, 255])
+            } else {
+                Rgba([230 - noise, 150 - noise, 40 + noise, 255])
+            }
+        });
+        let mut colors = median_cut(&image, 2);
+        colors.sort_by_key(|c| c.red);
+        let [dark, light] = colors[..] else {
+            panic!("expected two colors, got {colors:?}");
+        };
+        assert!((20..=28).contains(&dark.red) && (120..=128).contains(&dark.blue));
+        assert!((222..=230).contains(&light.red) && (40..=48).contains(&light.blue));
+    }
+
+    /// Red on the left half, a transparent blue on the right half.
+    fn half_transparent() -> RgbaImage {
+        RgbaImage::from_fn(8, 8, |x, _| {
+            if x < 4 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn transparent_pixels_are_ignored() {
+        let image = half_transparent();
+        let red = vec![Srgb::new(255, 0, 0)];
+        assert_eq!(median_cut(&image, 4), red);
+        assert_eq!(kmeans::<Srgb>(&image, 4, 10, 0), red);
+    }
+}
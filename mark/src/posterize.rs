@@ -0,0 +1,71 @@
+//! Reducing each channel of an image to a few evenly spaced values.
+
+use image::RgbaImage;
+
+/// Quantize a channel value to the nearest of `levels` evenly spaced values.
+fn quantize(value: u8, levels: u32) -> u8 {
+    let steps = (levels - 1) as f32;
+    let level = (value as f32 / 255.0 * steps).round();
+    (level / steps * 255.0).round() as u8
+}
+
+/// Reduce the srgb channels of an image to a number of evenly spaced levels
+/// per channel, given as R,G,B.
+///
+/// Two levels only allow 0 and 255, 256 levels leave the image unchanged. The
+/// alpha channel is not modified.
+///
+/// # Panics
+///
+/// Panics if any number of levels is outside the range `2..=256`.
+pub fn posterize(image: &mut RgbaImage, levels: [u32; 3]) {
+    assert!(
+        levels.iter().all(|l| (2..=256).contains(l)),
+        "levels must be between 2 and 256"
+    );
+    for pixel in image.pixels_mut() {
+        for (channel, levels) in pixel.0.iter_mut().zip(levels) {
+            *channel = quantize(*channel, levels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use image::Rgba;
+
+    use super::*;
+
+    /// Every value of each channel, and a varying alpha.
+    fn all_values() -> RgbaImage {
+        RgbaImage::from_fn(256, 3, |x, y| {
+            let v = x as u8;
+            Rgba([v, v.wrapping_add(85 * y as u8), 255 - v, v / 2])
+        })
+    }
+
+    fn channel_values(image: &RgbaImage, channel: usize) -> BTreeSet<u8> {
+        image.pixels().map(|p| p.0[channel]).collect()
+    }
+
+    #[test]
+    fn two_levels_give_two_values_per_channel() {
+        let mut image = all_values();
+        posterize(&mut image, [2, 2, 2]);
+        for channel in 0..3 {
+            assert_eq!(channel_values(&image, channel), BTreeSet::from([0, 255]));
+        }
+        assert_eq!(channel_values(&image, 3).len(), 128);
+    }
+
+    #[test]
+    fn levels_apply_per_channel() {
+        let mut image = all_values();
+        posterize(&mut image, [2, 3, 256]);
+        assert_eq!(channel_values(&image, 0), BTreeSet::from([0, 255]));
+        assert_eq!(channel_values(&image, 1), BTreeSet::from([0, 128, 255]));
+        assert_eq!(channel_values(&image, 2).len(), 256);
+    }
+}
@@ -0,0 +1,90 @@
+use image::{imageops, imageops::FilterType, Rgba, Rgba32FImage, RgbaImage};
+use palette::{Clamp, LinSrgb, Srgb};
+
+use crate::util;
+
+/// Resize an image in linear light.
+///
+/// Resizing gamma-encoded srgb values directly darkens high-contrast areas, so
+/// the image is converted to linear srgb first and converted back afterwards.
+pub fn resize_linear(image: &RgbaImage, width: u32, height: u32, filter: FilterType) -> RgbaImage {
+    let linear = Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = *image.get_pixel(x, y);
+        let color: LinSrgb = util::pixel_to_srgb(pixel).into_linear();
+        Rgba([
+            color.red,
+            color.green,
+            color.blue,
+            pixel.0[3] as f32 / 255.0,
+        ])
+    });
+
+    let resized = imageops::resize(&linear, width, height, filter);
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let [r, g, b, a] = resized.get_pixel(x, y).0;
+        let color = Srgb::from_linear(LinSrgb::new(r, g, b)).clamp();
+        let mut pixel = Rgba([0, 0, 0, (a.clamp(0.0, 1.0) * 255.0).round() as u8]);
+        util::update_pixel_with_srgb(&mut pixel, color);
+        pixel
+    })
+}
+
+/// Resize an image, either in linear light or directly on the gamma-encoded
+/// srgb values.
+pub fn resize(
+    image: &RgbaImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+    linear: bool,
+) -> RgbaImage {
+    if linear {
+        resize_linear(image, width, height, filter)
+    } else {
+        imageops::resize(image, width, height, filter)
+    }
+}
+
+/// Like [`resize`], but for images with channels in the range `0..=1`, for
+/// example decoded from 16 bit images.
+pub fn resize_f32(
+    image: &Rgba32FImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+    linear: bool,
+) -> Rgba32FImage {
+    if !linear {
+        return imageops::resize(image, width, height, filter);
+    }
+
+    let mut linear = image.clone();
+    for pixel in linear.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let color: LinSrgb = Srgb::new(r, g, b).into_linear();
+        *pixel = Rgba([color.red, color.green, color.blue, a]);
+    }
+
+    let mut resized = imageops::resize(&linear, width, height, filter);
+    for pixel in resized.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let color = Srgb::from_linear(LinSrgb::new(r, g, b)).clamp();
+        *pixel = Rgba([color.red, color.green, color.blue, a.clamp(0.0, 1.0)]);
+    }
+    resized
+}
+
+/// Upscale an image by an integer factor, turning each pixel into a
+/// `factor`x`factor` block.
+///
+/// # Panics
+///
+/// Panics if the factor is zero or the scaled size doesn't fit in a `u32`.
+pub fn pixel_scale(image: &RgbaImage, factor: u32) -> RgbaImage {
+    assert!(factor > 0, "pixel scale factor must not be zero");
+    let scaled = |side: u32| side.checked_mul(factor).expect("scaled image is too large");
+    RgbaImage::from_fn(scaled(image.width()), scaled(image.height()), |x, y| {
+        *image.get_pixel(x / factor, y / factor)
+    })
+}
@@ -0,0 +1,77 @@
+//! Dithering tuned for thermal printers.
+//!
+//! Thermal printers can only print black dots on white paper, and those dots
+//! bleed into their surroundings. Naively dithered images thus come out too
+//! dark, especially in the midtones.
+
+use image::RgbaImage;
+use palette::{IntoColor, Oklab, Srgb};
+
+use crate::{
+    dither::{
+        AlgoFloydSteinberg, AlgoStucki, Algorithm, Control, DiffEuclid, Options, Palette, TimedOut,
+    },
+    util,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Receipt printers with coarse dots that bleed a lot.
+    Receipt,
+    /// Label printers with finer and more precise dots.
+    Label,
+}
+
+impl Profile {
+    fn contrast(self) -> f32 {
+        match self {
+            Self::Receipt => 1.3,
+            Self::Label => 1.15,
+        }
+    }
+
+    /// How much to lighten the midtones to compensate for bleeding dots.
+    fn dot_gain(self) -> f32 {
+        match self {
+            Self::Receipt => 0.3,
+            Self::Label => 0.15,
+        }
+    }
+
+    fn adjust(self, pixel: Srgb) -> Srgb {
+        let mut pixel: Oklab = pixel.into_color();
+        let l = ((pixel.l - 0.5) * self.contrast() + 0.5).clamp(0.0, 1.0);
+        pixel.l = l.powf(1.0 - self.dot_gain());
+        pixel.a = 0.0;
+        pixel.b = 0.0;
+        pixel.into_color()
+    }
+}
+
+/// Convert an image into black dots on white paper.
+///
+/// Transparent areas are treated as blank paper.
+pub fn thermal(
+    mut image: RgbaImage,
+    profile: Profile,
+    control: &Control,
+) -> Result<RgbaImage, TimedOut> {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel.0[3] as f32 / 255.0;
+        let srgb = util::pixel_to_srgb(*pixel);
+        let srgb = srgb * alpha + Srgb::new(1.0, 1.0, 1.0) * (1.0 - alpha);
+        util::update_pixel_with_srgb(pixel, profile.adjust(srgb));
+        pixel.0[3] = 255;
+    }
+
+    let black = Oklab::new(0.0, 0.0, 0.0);
+    let white = Oklab::new(1.0, 0.0, 0.0);
+    let palette = Palette::new(vec![black, white]);
+    let options = Options::new();
+    match profile {
+        Profile::Receipt => {
+            AlgoFloydSteinberg::run(image, &palette, &DiffEuclid, &options, control)
+        }
+        Profile::Label => AlgoStucki::run(image, &palette, &DiffEuclid, &options, control),
+    }
+}
@@ -13,6 +13,15 @@ pub fn update_pixel_with_srgb(pixel: &mut Rgba<u8>, srgb: Srgb) {
     pixel.0[2] = srgb.blue;
 }
 
+/// Whether a color stays the same when clamped and converted to 8 bits per
+/// channel.
+pub fn is_within_srgb_gamut(srgb: Srgb) -> bool {
+    let tolerance = 0.5 / 255.0;
+    [srgb.red, srgb.green, srgb.blue]
+        .into_iter()
+        .all(|c| (-tolerance..=1.0 + tolerance).contains(&c))
+}
+
 pub fn pixel_to_color<C>(pixel: Rgba<u8>) -> C
 where
     Srgb: IntoColor<C>,